@@ -18,15 +18,15 @@ fn main() {
     for bsp_path in &maps {
         let map_name = bsp_path.file_stem().unwrap().to_str().unwrap();
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
+        let reader = BspReader::read(data).unwrap();
 
         let entity_string = resolve_map_entity_string(&reader);
 
         // Go through every entity and collect model references
-        let entities = BspEntity::parse_entities(&entity_string);
+        let entities = BspEntity::parse_entities(&entity_string).unwrap();
         let mut model_references = HashMap::<usize, Vec<usize>>::new();
         for (entity_index, entity) in entities.iter().enumerate() {
-            if let Some(model_str) = entity.0.get("model") {
+            if let Some(model_str) = entity.get("model") {
                 if model_str.starts_with("*") {
                     let model_index_str = &model_str[1..];
                     let model_index: usize = model_index_str.parse().unwrap();
@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use gsparser::bsp::{BspFile, BspReader};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let wad_path = args.get(1).expect("Expected wad output path!");
+    // Writes a copy of the map that loads the textures from the new wad
+    let stripped_path = args.get(2);
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    let extracted = reader.extract_embedded_textures();
+    let textures = reader.read_textures();
+    for texture_index in &extracted.texture_indices {
+        let name = textures
+            .get(*texture_index)
+            .map(|x| x.get_image_name())
+            .unwrap_or("missing");
+        println!("Extracted {} ({})", name, texture_index);
+    }
+    for texture_index in &extracted.skipped {
+        println!("Skipped texture {}", texture_index);
+    }
+    let wad_file = std::fs::File::create(wad_path).expect("Failed to create wad!");
+    extracted.wad.write(wad_file).expect("Failed to write wad!");
+
+    if let Some(stripped_path) = stripped_path {
+        let wad_name = Path::new(wad_path)
+            .file_name()
+            .and_then(|x| x.to_str())
+            .expect("Expected a wad file name!");
+        let mut bsp_file = BspFile::from_reader(&reader);
+        bsp_file
+            .strip_textures(&extracted.texture_indices, wad_name)
+            .expect("Failed to strip textures!");
+        let bsp = std::fs::File::create(stripped_path).expect("Failed to create bsp!");
+        bsp_file.write(bsp).expect("Failed to write bsp!");
+    }
+}
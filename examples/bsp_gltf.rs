@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use gsparser::bsp::{BspGltfOptions, BspReader};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let output_path = PathBuf::from(args.get(1).expect("Expected output path!"));
+    // Any remaining args are directories to search for wads
+    let search_dirs: Vec<PathBuf> = args.iter().skip(2).map(PathBuf::from).collect();
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::read(file_bytes).expect("Failed to parse bsp!");
+
+    let options = BspGltfOptions {
+        unit_scale: 0.0254,
+        search_dirs,
+        ..Default::default()
+    };
+    let gltf = reader.export_gltf(&options);
+
+    let is_glb = output_path
+        .extension()
+        .map(|x| x.eq_ignore_ascii_case("glb"))
+        .unwrap_or(false);
+    if is_glb {
+        gltf.write_glb(&output_path).expect("Failed to write glb!");
+    } else {
+        gltf.write_gltf(&output_path)
+            .expect("Failed to write gltf!");
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt::Write;
+
+use gsparser::bsp::{BspHull, BspReader};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let output_path = args.get(1).expect("Expected output path!");
+    let hull = args
+        .get(2)
+        .and_then(|x| x.parse::<usize>().ok())
+        .and_then(BspHull::from_index)
+        .unwrap_or(BspHull::Standing);
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    let mut obj = String::new();
+    let mut vertex_offset = 1;
+    for model_index in 0..reader.read_models().len() {
+        let brushes = reader.build_hull_brushes(model_index, hull);
+        println!("Model {}: {} brushes", model_index, brushes.len());
+        for (brush_index, brush) in brushes.iter().enumerate() {
+            writeln!(
+                &mut obj,
+                "o model_{}_brush_{}_{:?}",
+                model_index, brush_index, brush.contents
+            )
+            .unwrap();
+            for [x, y, z] in &brush.vertices {
+                writeln!(&mut obj, "v {} {} {}", x, y, z).unwrap();
+            }
+            for face in &brush.faces {
+                write!(&mut obj, "f").unwrap();
+                for index in face {
+                    write!(&mut obj, " {}", *index as usize + vertex_offset).unwrap();
+                }
+                writeln!(&mut obj).unwrap();
+            }
+            vertex_offset += brush.vertices.len();
+        }
+    }
+
+    std::fs::write(output_path, obj).expect("Failed to write file!");
+}
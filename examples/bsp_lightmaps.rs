@@ -0,0 +1,25 @@
+use gsparser::bsp::BspReader;
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let output_path = args.get(1).expect("Expected output path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::read(file_bytes).expect("Failed to parse bsp!");
+
+    let atlas = reader.build_lightmap_atlas();
+    let lit_faces = atlas.entries.iter().filter(|x| x.is_some()).count();
+    println!(
+        "Packed {} of {} faces into a {}x{} atlas",
+        lit_faces,
+        atlas.entries.len(),
+        atlas.image.width(),
+        atlas.image.height()
+    );
+
+    atlas
+        .image
+        .save(output_path)
+        .expect("Failed to save atlas!");
+}
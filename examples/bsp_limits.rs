@@ -0,0 +1,21 @@
+use gsparser::bsp::BspReader;
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    let report = reader.check_limits();
+    print!("{}", report);
+    for limit in report.exceeded() {
+        if !limit.offenders.is_empty() {
+            println!(
+                "{} offending faces: {:?}",
+                limit.kind.name(),
+                limit.offenders
+            );
+        }
+    }
+}
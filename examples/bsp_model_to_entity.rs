@@ -2,11 +2,8 @@ extern crate glob;
 extern crate gsparser;
 
 use glob::glob;
-use gsparser::{
-    bsp::{BspEntity, BspReader},
-    util::resolve_map_entity_string,
-};
-use std::{collections::HashSet, path::PathBuf};
+use gsparser::bsp::{BspModelInstances, BspReader};
+use std::path::PathBuf;
 
 fn main() {
     let args = std::env::args().skip(1).collect::<Vec<_>>();
@@ -18,31 +15,16 @@ fn main() {
     for bsp_path in &maps {
         let map_name = bsp_path.file_stem().unwrap().to_str().unwrap();
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
-
-        let entity_string = resolve_map_entity_string(&reader);
-
-        // Go through every entity and collect model references
-        let entities = BspEntity::parse_entities(&entity_string);
-        let mut model_references = HashSet::new();
-        for entity in entities {
-            if let Some(model_str) = entity.0.get("model") {
-                if model_str.starts_with("*") {
-                    let index_str = &model_str[1..];
-                    let index: usize = index_str.parse().unwrap();
-                    model_references.insert(index);
-                }
-            }
-        }
-
-        // Go through each map (skip the first, that's never an entity)
-        let mut models_with_no_entities = Vec::new();
-        let models = reader.read_models();
-        for model_index in 1..models.len() {
-            if !model_references.contains(&model_index) {
-                models_with_no_entities.push(model_index);
-            }
-        }
+        let reader = BspReader::read(data).unwrap();
+
+        // The world model is never an entity, skip it
+        let instances = BspModelInstances::from_reader(&reader).unwrap();
+        let models_with_no_entities: Vec<_> = instances
+            .instances
+            .iter()
+            .filter(|x| x.model_index != 0 && x.entity_index.is_none())
+            .map(|x| x.model_index)
+            .collect();
 
         if !models_with_no_entities.is_empty() {
             map_info.push((map_name.to_string(), models_with_no_entities));
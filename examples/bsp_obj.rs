@@ -0,0 +1,49 @@
+use std::fmt::Write;
+
+use gsparser::bsp::BspReader;
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let output_path = args.get(1).expect("Expected output path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::read(file_bytes).expect("Failed to parse bsp!");
+    let textures = reader.read_textures();
+
+    let mut obj = String::new();
+    let mut vertex_offset = 1;
+    for model in reader.build_model_meshes() {
+        writeln!(&mut obj, "o model_{}", model.model_index).unwrap();
+        for mesh in &model.meshes {
+            let texture_name = textures
+                .get(mesh.texture_index as usize)
+                .map(|x| x.get_image_name())
+                .unwrap_or("missing");
+            writeln!(&mut obj, "g {}", texture_name).unwrap();
+            for vertex in &mesh.vertices {
+                let [x, y, z] = vertex.position;
+                writeln!(&mut obj, "v {} {} {}", x, y, z).unwrap();
+            }
+            for vertex in &mesh.vertices {
+                let [x, y, z] = vertex.normal;
+                writeln!(&mut obj, "vn {} {} {}", x, y, z).unwrap();
+            }
+            for vertex in &mesh.vertices {
+                let [u, v] = vertex.uv;
+                writeln!(&mut obj, "vt {} {}", u, -v).unwrap();
+            }
+            for triangle in mesh.indices.chunks_exact(3) {
+                write!(&mut obj, "f").unwrap();
+                for index in triangle {
+                    let index = *index as usize + vertex_offset;
+                    write!(&mut obj, " {}/{}/{}", index, index, index).unwrap();
+                }
+                writeln!(&mut obj).unwrap();
+            }
+            vertex_offset += mesh.vertices.len();
+        }
+    }
+
+    std::fs::write(output_path, obj).expect("Failed to write file!");
+}
@@ -0,0 +1,66 @@
+use gsparser::bsp::{BspContents, BspEntity, BspModelInstances, BspReader};
+use gsparser::util::resolve_map_entity_string;
+
+const SPAWN_CLASSNAMES: [&str; 2] = ["info_player_start", "info_player_deathmatch"];
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+    let entity_string = resolve_map_entity_string(&reader);
+    let entities = BspEntity::parse_entities(&entity_string).expect("Failed to parse entities!");
+    let instances = BspModelInstances::new(&reader, &entities);
+    let graph = reader.build_leaf_graph();
+    let passable = |contents: BspContents| contents.is_passable();
+
+    let mut start_leaves = Vec::new();
+    for entity in &entities {
+        if !entity
+            .classname()
+            .is_some_and(|x| SPAWN_CLASSNAMES.contains(&x))
+        {
+            continue;
+        }
+        if let Some(leaf) = entity
+            .get_vector("origin")
+            .and_then(|origin| reader.find_leaf(0, origin))
+        {
+            start_leaves.push(leaf);
+        }
+    }
+    println!("Spawn leaves: {:?}", start_leaves);
+    let reached = graph.flood_fill(&start_leaves, passable);
+    // Leaves of other brush models aren't part of the graph
+    let is_reached = |leaf: &usize| reached.get(*leaf).copied().unwrap_or(false);
+
+    for (entity_index, entity) in entities.iter().enumerate() {
+        if entity.classname() != Some("trigger_changelevel") {
+            continue;
+        }
+        let Some(instance) = instances.get_by_entity(entity_index) else {
+            continue;
+        };
+        let leaves = reader.find_leaves_in_box(0, instance.mins, instance.maxs);
+        let is_reachable = leaves.iter().any(is_reached);
+        println!(
+            "trigger_changelevel {} ({}): {}",
+            entity_index,
+            entity.get("map").unwrap_or("?"),
+            if is_reachable {
+                "reachable"
+            } else {
+                "unreachable"
+            }
+        );
+    }
+
+    let regions = graph.find_regions(passable);
+    println!("Regions: {}", regions.len());
+    for region in regions {
+        if !region.iter().any(is_reached) {
+            println!("  Unreachable pocket: {:?}", region);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use gsparser::bsp::{BspFile, BspReader};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let output_path = args.get(1);
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    println!("Variant: {:?}", reader.variant());
+
+    let bsp_file = BspFile::from_reader(&reader);
+    let output_bytes = bsp_file.to_bytes();
+
+    if output_bytes == file_bytes {
+        println!("Round trip matches ({} bytes)", output_bytes.len());
+    } else {
+        let first_difference = output_bytes
+            .iter()
+            .zip(file_bytes.iter())
+            .position(|(a, b)| a != b)
+            .unwrap_or(output_bytes.len().min(file_bytes.len()));
+        println!(
+            "Round trip differs! input: {} bytes  output: {} bytes  first difference: {}",
+            file_bytes.len(),
+            output_bytes.len(),
+            first_difference
+        );
+    }
+
+    if let Some(output_path) = output_path {
+        std::fs::write(output_path, output_bytes).expect("Failed to write file!");
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use gsparser::bsp::BspReader;
+use gsparser::scene::{Scene, SceneInstanceKind};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    // The mod directory followed by any fallbacks, e.g. "valve"
+    let search_dirs = &args[1..];
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    let scene = Scene::from_reader(&reader, Path::new(path), search_dirs)
+        .expect("Failed to parse entities!");
+    for instance in &scene.instances {
+        let [x, y, z] = instance.transform.origin;
+        let kind = match &instance.kind {
+            SceneInstanceKind::World => "world".to_owned(),
+            SceneInstanceKind::Brush { model_index } => format!("brush {}", model_index),
+            SceneInstanceKind::Studio {
+                body,
+                skin,
+                sequence,
+            } => format!("studio body {} skin {} sequence {}", body, skin, sequence),
+            SceneInstanceKind::Sprite { scale, frame_rate } => {
+                format!("sprite scale {} frame rate {}", scale, frame_rate)
+            }
+        };
+        println!(
+            "{:?} {} ({}) at ({} {} {}) {:?} {} -> {:?}",
+            instance.entity_index,
+            instance.classname,
+            kind,
+            x,
+            y,
+            z,
+            instance.render.mode,
+            instance.model,
+            instance.path
+        );
+    }
+    for instance in scene.missing_files() {
+        println!("Missing {}", instance.model);
+    }
+}
@@ -0,0 +1,17 @@
+use gsparser::bsp::{BspReader, BspSpawnReport};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    let report = BspSpawnReport::from_reader(&reader).expect("Failed to parse entities!");
+    print!("{}", report);
+    println!(
+        "{} of {} spawns have problems",
+        report.problems().count(),
+        report.spawns.len()
+    );
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use gsparser::bsp::BspReader;
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+    let output_path = args.get(1).expect("Expected output path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::read(file_bytes).expect("Failed to parse bsp!");
+
+    let mut output_path = PathBuf::from(output_path);
+    if !output_path.exists() {
+        std::fs::create_dir_all(&output_path).expect("Failed to make output directory!");
+    }
+    output_path.push("dummy");
+
+    let textures = reader.read_textures();
+    println!("Textures ({}):", textures.len());
+    for i in 0..textures.len() {
+        let Some(texture) = textures.get(i) else {
+            println!("  {} - missing", i);
+            continue;
+        };
+        let name = texture.get_image_name();
+        if let Some(texture_data) = texture.decode() {
+            println!(
+                "  {} - {} ({}x{}, {:?})",
+                i,
+                name,
+                texture_data.image_width,
+                texture_data.image_height,
+                texture.kind()
+            );
+            output_path.set_file_name(format!("{}.png", name));
+            texture_data
+                .image
+                .save_with_format(&output_path, image::ImageFormat::Png)
+                .unwrap();
+        } else {
+            println!("  {} - {} (external, {:?})", i, name, texture.kind());
+        }
+    }
+
+    let animations = reader.get_texture_animations();
+    if !animations.is_empty() {
+        println!("Animations:");
+        for animation in animations {
+            println!(
+                "  {} - frames: {:?}  alternate: {:?}",
+                animation.base_name, animation.frames, animation.alternate_frames
+            );
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use gsparser::bsp::{BspEntityGraph, BspReader};
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::read(file_bytes).expect("Failed to parse bsp!");
+    let graph = BspEntityGraph::from_reader(&reader).expect("Failed to parse entities!");
+
+    println!("Dangling links:");
+    for link in graph.get_dangling_links() {
+        let source = &graph.entities[link.source];
+        println!(
+            "  {} ({}) -> {:?} \"{}\"",
+            link.source, source.classname, link.kind, link.name
+        );
+    }
+
+    println!("Unreachable triggers:");
+    for entity_index in graph.get_unreachable_triggers() {
+        let entity = &graph.entities[entity_index];
+        println!(
+            "  {} ({}) {:?}",
+            entity_index, entity.classname, entity.targetname
+        );
+    }
+
+    println!("Cycles:");
+    for cycle in graph.find_cycles() {
+        println!("  {:?}", cycle);
+    }
+
+    println!("Fire chains:");
+    for (entity_index, entity) in graph.entities.iter().enumerate() {
+        if !entity.classname.starts_with("func_button") && !entity.classname.starts_with("trigger_")
+        {
+            continue;
+        }
+        let chain = graph.get_fire_chain(entity_index);
+        if chain.is_empty() {
+            continue;
+        }
+        println!("  {} ({}):", entity_index, entity.classname);
+        for event in chain {
+            let target = &graph.entities[event.entity];
+            println!(
+                "    {:>6.2}s  {} ({}) {:?}",
+                event.time, event.entity, target.classname, target.targetname
+            );
+        }
+    }
+}
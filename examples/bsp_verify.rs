@@ -0,0 +1,18 @@
+use gsparser::bsp::BspReader;
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let path = args.get(0).expect("Expected import path!");
+
+    let file_bytes = std::fs::read(path).expect("Failed to open file!");
+    let reader = BspReader::from_slice(&file_bytes).expect("Failed to parse bsp!");
+
+    let issues = reader.verify_integrity();
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    println!("{} issues found", issues.len());
+    if !issues.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -5,7 +5,7 @@ fn main() {
     let path = args.get(0).expect("Expected import path!");
 
     let file_bytes = std::fs::read(path).expect("Failed to open file!");
-    let reader = BspReader::read(file_bytes);
+    let reader = BspReader::read(file_bytes).expect("Failed to parse bsp!");
 
     let vertices = reader.read_vertices();
     println!("Vertices ({}):", vertices.len());
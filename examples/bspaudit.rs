@@ -33,7 +33,7 @@ fn print_max_num_textures(paths: &[PathBuf]) {
     let mut max = u32::MIN;
     for bsp_path in paths {
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
+        let reader = BspReader::read(data).unwrap();
         let textures = reader.read_textures_header();
         let num_textures = textures.num_textures;
 
@@ -47,12 +47,12 @@ fn print_entity_types(paths: &[PathBuf]) {
     let mut entity_types = HashMap::<String, usize>::new();
     for bsp_path in paths {
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
+        let reader = BspReader::read(data).unwrap();
 
         let entity_string = resolve_map_entity_string(&reader);
-        let entities = BspEntity::parse_entities(&entity_string);
+        let entities = BspEntity::parse_entities(&entity_string).unwrap();
         for entity in &entities {
-            let entity_type = *entity.0.get("classname").unwrap();
+            let entity_type = entity.classname().unwrap();
 
             if let Some(count) = entity_types.get_mut(entity_type) {
                 *count += 1;
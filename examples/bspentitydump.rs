@@ -22,7 +22,7 @@ fn main() {
     for bsp_path in &maps {
         let map_name = bsp_path.file_stem().unwrap().to_str().unwrap();
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
+        let reader = BspReader::read(data).unwrap();
 
         let entity_string = resolve_map_entity_string(&reader);
         output_path.set_file_name(format!("{}.txt", map_name));
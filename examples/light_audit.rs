@@ -38,17 +38,17 @@ fn main() {
     for bsp_path in &maps {
         let map_name = bsp_path.file_stem().unwrap().to_str().unwrap();
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
+        let reader = BspReader::read(data).unwrap();
 
         let entity_string = resolve_map_entity_string(&reader);
-        let entities = BspEntity::parse_entities(&entity_string);
+        let entities = BspEntity::parse_entities(&entity_string).unwrap();
         let mut seen_light_styles = HashSet::new();
         for entity in &entities {
-            let entity_type = *entity.0.get("classname").unwrap();
+            let entity_type = entity.classname().unwrap();
             if entity_type.starts_with("light") {
-                if let Some(style) = entity.0.get("style") {
-                    if *style != "0" {
-                        if !seen_light_styles.contains(*style) {
+                if let Some(style) = entity.get("style") {
+                    if style != "0" {
+                        if !seen_light_styles.contains(style) {
                             seen_light_styles.insert(style.to_owned());
                         }
 
@@ -29,13 +29,13 @@ fn main() {
     let mut min_monsters = i32::MAX;
     for bsp_path in &maps {
         let data = std::fs::read(bsp_path).unwrap();
-        let reader = BspReader::read(data);
+        let reader = BspReader::read(data).unwrap();
 
         let entity_string = resolve_map_entity_string(&reader);
-        let entities = BspEntity::parse_entities(&entity_string);
+        let entities = BspEntity::parse_entities(&entity_string).unwrap();
         let mut total_monsters_on_map = 0;
         for entity in &entities {
-            let entity_type = *entity.0.get("classname").unwrap();
+            let entity_type = entity.classname().unwrap();
             if entity_type.starts_with("monster_") {
                 total_monsters_on_map += 1;
             }
@@ -63,9 +63,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 };
                 println!("  Loading map from {}", map_path.display());
                 let bsp_data = std::fs::read(map_path)?;
-                let reader = BspReader::read(bsp_data);
+                let reader = BspReader::read(bsp_data)?;
                 let entity_string = resolve_map_entity_string(&reader);
-                let entities = BspEntity::parse_entities(&entity_string);
+                let entities = BspEntity::parse_entities(&entity_string)?;
                 let num_entities = entities.len();
 
                 println!(
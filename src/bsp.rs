@@ -1,11 +1,62 @@
 // Sources:
 // https://developer.valvesoftware.com/wiki/BSP_(GoldSrc)
 
-use std::collections::HashMap;
-
 use serde::Deserialize;
 
-use crate::util::null_terminated_bytes_to_str;
+use crate::util::{NullTerminatedStrError, null_terminated_bytes_to_str};
+use crate::wad3::MipmapedTextureData;
+
+mod entity;
+mod entity_graph;
+mod file;
+mod gltf;
+mod hull_brush;
+mod integrity;
+mod light_point;
+mod light_style;
+mod lightmap;
+mod limits;
+mod mesh;
+mod model_instance;
+mod portal;
+mod spawn;
+#[cfg(test)]
+mod test_maps;
+mod texture;
+mod trace;
+mod tree;
+mod vis;
+mod wad;
+mod winding;
+
+pub use entity::{
+    BspEntity, BspEntityParseError, BspEntityParseErrorKind, BspEntitySerializeError,
+};
+pub use entity_graph::{
+    BspEntityGraph, BspEntityLink, BspEntityLinkKind, BspEntityNode, BspFireEvent,
+};
+pub use file::{BspFile, COMPILER_LUMP_ORDER};
+pub use gltf::{BspGltf, BspGltfOptions, goldsrc_to_gltf};
+pub use hull_brush::BspHullBrush;
+pub use integrity::BspIntegrityIssue;
+pub use light_point::BspLightSample;
+pub use light_style::{BspLightStyles, FIRST_SWITCHABLE_LIGHT_STYLE, MAX_LIGHT_STYLE_PATTERNS};
+pub use lightmap::{
+    BspAtlasRect, BspFaceExtents, BspFaceLightmap, BspLightmapAtlas, BspLightmapAtlasEntry,
+    BspLightmapStyle, LUXEL_SIZE, MAX_LIGHT_STYLES, NO_LIGHT_STYLE,
+};
+pub use limits::{BspLimit, BspLimitKind, BspLimitReport, MAX_SURFACE_EXTENT};
+pub use mesh::{BspMeshFace, BspMeshVertex, BspModelMesh, BspTextureMesh};
+pub use model_instance::{BspModelInstance, BspModelInstances, BspTransform};
+pub use portal::{BspLeafGraph, BspLeafPortal};
+pub use spawn::{BspSpawnCheck, BspSpawnProblem, BspSpawnReport};
+pub use texture::{BspTextureAnimation, BspTextureKind, TEX_SPECIAL, get_texture_base_name};
+pub use trace::{BspHull, BspTrace, BspTracePlane};
+pub use vis::BspPvs;
+pub use wad::{
+    BspExtractedTextures, BspResolvedTexture, BspResolvedTextures, BspStripTexturesError,
+    BspTextureSource, parse_wad_list,
+};
 
 macro_rules! enum_with_value {
     ($name:ident : $value_ty:ty { $($var_name:ident = $var_value:literal),* $(,)* }) => {
@@ -189,23 +240,275 @@ pub struct BspClipNode {
     pub children: [i16; 2],
 }
 
-// TODO: Borrow data
-pub struct BspReader {
+pub const LUMP_NAMES: [&str; HEADER_LUMPS] = [
+    "Entities",
+    "Planes",
+    "Textures",
+    "Vertices",
+    "Visibility",
+    "Nodes",
+    "Texture Infos",
+    "Faces",
+    "Lighting",
+    "Clip Nodes",
+    "Leaves",
+    "Mark Surfaces",
+    "Edges",
+    "Surface Edges",
+    "Models",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspLumpError {
+    NegativeOffset(i32),
+    NegativeLength(i32),
+    OutOfBounds {
+        end: usize,
+        file_len: usize,
+    },
+    BadElementSize {
+        len: usize,
+        element_size: usize,
+    },
+    TruncatedTextureHeader,
+    TextureOffsetsOutOfBounds {
+        num_textures: u32,
+    },
+    TextureOutOfBounds {
+        texture_index: usize,
+        offset: i32,
+    },
+    MipOutOfBounds {
+        texture_index: usize,
+        mip_level: usize,
+    },
+    BadTextureName {
+        texture_index: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum BspReadError {
+    TruncatedHeader { len: usize },
+    UnsupportedVersion(i32),
+    InvalidLump { lump: usize, error: BspLumpError },
+}
+
+impl std::fmt::Display for BspLumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BspLumpError::NegativeOffset(offset) => write!(f, "negative offset {}", offset),
+            BspLumpError::NegativeLength(len) => write!(f, "negative length {}", len),
+            BspLumpError::OutOfBounds { end, file_len } => {
+                write!(f, "ends at {} but the file is {} bytes", end, file_len)
+            }
+            BspLumpError::BadElementSize { len, element_size } => write!(
+                f,
+                "length {} is not a multiple of the element size {}",
+                len, element_size
+            ),
+            BspLumpError::TruncatedTextureHeader => write!(f, "texture header is truncated"),
+            BspLumpError::TextureOffsetsOutOfBounds { num_textures } => write!(
+                f,
+                "offset table for {} textures does not fit in the lump",
+                num_textures
+            ),
+            BspLumpError::TextureOutOfBounds {
+                texture_index,
+                offset,
+            } => write!(
+                f,
+                "texture {} at offset {} does not fit in the lump",
+                texture_index, offset
+            ),
+            BspLumpError::MipOutOfBounds {
+                texture_index,
+                mip_level,
+            } => write!(
+                f,
+                "mip level {} of texture {} does not fit in the lump",
+                mip_level, texture_index
+            ),
+            BspLumpError::BadTextureName { texture_index } => {
+                write!(f, "name of texture {} is not valid UTF-8", texture_index)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BspReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BspReadError::TruncatedHeader { len } => {
+                write!(f, "file is too small for a bsp header ({} bytes)", len)
+            }
+            BspReadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bsp version {}", version)
+            }
+            BspReadError::InvalidLump { lump, error } => {
+                write!(
+                    f,
+                    "invalid {} lump ({}): {}",
+                    LUMP_NAMES[*lump], lump, error
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BspReadError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspVariant {
+    /// Version 30, used by Half-Life and most GoldSrc games.
+    GoldSrc,
+    /// Version 30 with the entities and planes lump entries swapped in the header.
+    BlueShift,
+    /// Version 29, textures don't carry their own palette.
+    Quake,
+}
+
+impl BspVariant {
+    pub fn version(&self) -> i32 {
+        match self {
+            BspVariant::GoldSrc | BspVariant::BlueShift => 30,
+            BspVariant::Quake => 29,
+        }
+    }
+
+    /// Returns true if embedded textures are followed by a palette.
+    pub fn has_texture_palettes(&self) -> bool {
+        *self != BspVariant::Quake
+    }
+
+    /// Returns the size of a lightmap luxel, 1 for Quake's mono lightmaps and 3
+    /// for RGB.
+    pub fn lightmap_luxel_size(&self) -> usize {
+        match self {
+            BspVariant::GoldSrc | BspVariant::BlueShift => 3,
+            BspVariant::Quake => 1,
+        }
+    }
+
+    /// Swaps the lump entries that Blue Shift stores in a different order. The
+    /// swap is its own inverse, so this converts in both directions.
+    pub fn swap_lumps(&self, lumps: &mut [BspLumpHeader; HEADER_LUMPS]) {
+        if *self == BspVariant::BlueShift {
+            lumps.swap(LUMP_ENTITIES, LUMP_PLANES);
+        }
+    }
+}
+
+enum BspData<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for BspData<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BspData::Borrowed(data) => data,
+            BspData::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            BspData::Mapped(data) => data,
+        }
+    }
+}
+
+pub struct BspReader<'a> {
     header: BspHeader,
-    data: Vec<u8>,
+    variant: BspVariant,
+    data: BspData<'a>,
+    // Copies of lumps that aren't aligned for their element type in `data`
+    aligned_lumps: [Option<Box<[u32]>>; HEADER_LUMPS],
 }
 
-impl BspReader {
-    pub fn read(data: Vec<u8>) -> Self {
-        let header: BspHeader = bincode::deserialize(&data).unwrap();
-        assert_eq!(header.version, 30);
-        Self { header, data }
+impl BspReader<'static> {
+    pub fn read(data: Vec<u8>) -> Result<Self, BspReadError> {
+        Self::new(BspData::Owned(data))
     }
 
+    /// Maps the file into memory instead of reading it. The file must not be
+    /// modified while the reader is alive.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let data = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(BspData::Mapped(data))
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+}
+
+impl<'a> BspReader<'a> {
+    pub fn from_slice(data: &'a [u8]) -> Result<Self, BspReadError> {
+        Self::new(BspData::Borrowed(data))
+    }
+
+    fn new(data: BspData<'a>) -> Result<Self, BspReadError> {
+        let header_size = std::mem::size_of::<BspHeader>();
+        if data.len() < header_size {
+            return Err(BspReadError::TruncatedHeader { len: data.len() });
+        }
+        let mut header: BspHeader = bincode::deserialize(&data[..header_size])
+            .map_err(|_| BspReadError::TruncatedHeader { len: data.len() })?;
+        let variant = match header.version {
+            29 => BspVariant::Quake,
+            30 if Self::is_blue_shift(&header, &data) => BspVariant::BlueShift,
+            30 => BspVariant::GoldSrc,
+            _ => return Err(BspReadError::UnsupportedVersion(header.version)),
+        };
+        variant.swap_lumps(&mut header.lumps);
+
+        let mut reader = Self {
+            header,
+            variant,
+            data,
+            aligned_lumps: Default::default(),
+        };
+        reader.validate_lumps()?;
+        reader.align_lumps();
+        reader
+            .validate_textures()
+            .map_err(|error| BspReadError::InvalidLump {
+                lump: LUMP_TEXTURES,
+                error,
+            })?;
+        Ok(reader)
+    }
+
+    /// Returns the header with lumps in the standard order, regardless of variant.
     pub fn header(&self) -> &BspHeader {
         &self.header
     }
 
+    pub fn variant(&self) -> BspVariant {
+        self.variant
+    }
+
+    // Blue Shift maps store the planes lump entry first. The entity lump is
+    // text starting with '{', planes never are.
+    fn is_blue_shift(header: &BspHeader, data: &[u8]) -> bool {
+        let looks_like_entities = |lump: &BspLumpHeader| {
+            if lump.offset < 0 || lump.len <= 0 {
+                return false;
+            }
+            let start = lump.offset as usize;
+            let end = start.saturating_add(lump.len as usize).min(data.len());
+            data.get(start..end)
+                .and_then(|x| x.iter().find(|x| !x.is_ascii_whitespace()))
+                == Some(&b'{')
+        };
+        let entities = &header.lumps[LUMP_ENTITIES];
+        let planes = &header.lumps[LUMP_PLANES];
+        !looks_like_entities(entities)
+            && looks_like_entities(planes)
+            && (entities.len as usize).is_multiple_of(size_of::<BspPlane>())
+    }
+
     pub fn read_nodes(&self) -> &[BspNode] {
         self.read_lump(LUMP_NODES)
     }
@@ -234,10 +537,15 @@ impl BspReader {
         self.read_lump(LUMP_VERTICES)
     }
 
-    pub fn read_textures<'a>(&'a self) -> BspTextureReader<'a> {
+    pub fn read_textures(&self) -> BspTextureReader<'_> {
         let raw_data = self.read_lump_raw(LUMP_TEXTURES);
+        let has_palettes = self.variant.has_texture_palettes();
+        if raw_data.is_empty() {
+            return BspTextureReader::new(&[], raw_data, has_palettes);
+        }
 
-        let header: BspTextureHeader = bincode::deserialize(&raw_data).unwrap();
+        // The offsets table was bounds checked by validate_textures
+        let header = self.read_textures_header();
         let offsets_start = std::mem::size_of::<BspTextureHeader>();
         let offsets_end =
             offsets_start + (std::mem::size_of::<i32>() * header.num_textures as usize);
@@ -247,13 +555,16 @@ impl BspReader {
             std::slice::from_raw_parts(ptr, header.num_textures as usize)
         };
 
-        BspTextureReader::new(offsets, raw_data)
+        BspTextureReader::new(offsets, raw_data, has_palettes)
     }
 
     pub fn read_textures_header(&self) -> BspTextureHeader {
         let raw_data = self.read_lump_raw(LUMP_TEXTURES);
+        if raw_data.is_empty() {
+            return BspTextureHeader { num_textures: 0 };
+        }
 
-        let header: BspTextureHeader = bincode::deserialize(&raw_data).unwrap();
+        let header: BspTextureHeader = bincode::deserialize(raw_data).unwrap();
         header
     }
 
@@ -269,8 +580,10 @@ impl BspReader {
         self.read_lump(LUMP_ENTITIES)
     }
 
-    pub fn read_entities_str(&self) -> &str {
-        null_terminated_bytes_to_str(self.read_entities()).unwrap()
+    /// Fails if the entity lump isn't UTF-8, see `resolve_map_entity_string` for
+    /// a lossy version.
+    pub fn read_entities_str(&self) -> Result<&str, NullTerminatedStrError> {
+        null_terminated_bytes_to_str(self.read_entities())
     }
 
     pub fn read_models(&self) -> &[BspModel] {
@@ -285,16 +598,160 @@ impl BspReader {
         self.read_lump_raw(LUMP_LIGHTING)
     }
 
+    pub fn read_visibility_data(&self) -> &[u8] {
+        self.read_lump_raw(LUMP_VISIBILITY)
+    }
+
+    // Element size and alignment of each lump
+    fn lump_layout(index: usize) -> (usize, usize) {
+        match index {
+            LUMP_PLANES => (size_of::<BspPlane>(), align_of::<BspPlane>()),
+            LUMP_TEXTURES => (1, align_of::<i32>()),
+            LUMP_VERTICES => (size_of::<BspVertex>(), align_of::<BspVertex>()),
+            LUMP_NODES => (size_of::<BspNode>(), align_of::<BspNode>()),
+            LUMP_TEXINFO => (size_of::<BspTextureInfo>(), align_of::<BspTextureInfo>()),
+            LUMP_FACES => (size_of::<BspFace>(), align_of::<BspFace>()),
+            LUMP_CLIPNODES => (size_of::<BspClipNode>(), align_of::<BspClipNode>()),
+            LUMP_LEAVES => (size_of::<BspLeaf>(), align_of::<BspLeaf>()),
+            LUMP_MARKSURFACES => (size_of::<BspMarkSurface>(), align_of::<BspMarkSurface>()),
+            LUMP_EDGES => (size_of::<BspEdge>(), align_of::<BspEdge>()),
+            LUMP_SURFEDGES => (size_of::<BspSurfaceEdge>(), align_of::<BspSurfaceEdge>()),
+            LUMP_MODELS => (size_of::<BspModel>(), align_of::<BspModel>()),
+            _ => (1, 1),
+        }
+    }
+
+    fn validate_lumps(&self) -> Result<(), BspReadError> {
+        for lump in 0..HEADER_LUMPS {
+            let (element_size, _) = Self::lump_layout(lump);
+            self.validate_lump(lump, element_size)
+                .map_err(|error| BspReadError::InvalidLump { lump, error })?;
+        }
+        Ok(())
+    }
+
+    fn validate_lump(&self, index: usize, element_size: usize) -> Result<(), BspLumpError> {
+        let lump_header = self.header.lumps[index];
+        if lump_header.offset < 0 {
+            return Err(BspLumpError::NegativeOffset(lump_header.offset));
+        }
+        if lump_header.len < 0 {
+            return Err(BspLumpError::NegativeLength(lump_header.len));
+        }
+        let offset = lump_header.offset as usize;
+        let len = lump_header.len as usize;
+        let end = offset + len;
+        if end > self.data.len() {
+            return Err(BspLumpError::OutOfBounds {
+                end,
+                file_len: self.data.len(),
+            });
+        }
+        if !len.is_multiple_of(element_size) {
+            return Err(BspLumpError::BadElementSize { len, element_size });
+        }
+        Ok(())
+    }
+
+    // Lumps are cast in place when they're aligned, which is always the case for
+    // compiler output in a heap or mapped buffer. Anything else (odd lump offsets,
+    // a sub-slice of a larger buffer) is copied into aligned storage once.
+    fn align_lumps(&mut self) {
+        for index in 0..HEADER_LUMPS {
+            let (_, align) = Self::lump_layout(index);
+            let data = self.read_lump_raw(index);
+            // Empty lumps are never dereferenced, so their offset doesn't matter
+            if data.is_empty() || (data.as_ptr() as usize).is_multiple_of(align) {
+                continue;
+            }
+            let mut words = vec![0u32; data.len().div_ceil(size_of::<u32>())].into_boxed_slice();
+            for (word, bytes) in words.iter_mut().zip(data.chunks(size_of::<u32>())) {
+                let mut word_bytes = [0u8; 4];
+                word_bytes[..bytes.len()].copy_from_slice(bytes);
+                *word = u32::from_ne_bytes(word_bytes);
+            }
+            self.aligned_lumps[index] = Some(words);
+        }
+    }
+
+    fn validate_textures(&self) -> Result<(), BspLumpError> {
+        let raw_data = self.read_lump_raw(LUMP_TEXTURES);
+        if raw_data.is_empty() {
+            return Ok(());
+        }
+        let header_size = size_of::<BspTextureHeader>();
+        if raw_data.len() < header_size {
+            return Err(BspLumpError::TruncatedTextureHeader);
+        }
+        let header = self.read_textures_header();
+        let offsets_end = (header.num_textures as usize)
+            .checked_mul(size_of::<i32>())
+            .and_then(|x| x.checked_add(header_size));
+        if offsets_end.is_none_or(|end| end > raw_data.len()) {
+            return Err(BspLumpError::TextureOffsetsOutOfBounds {
+                num_textures: header.num_textures,
+            });
+        }
+
+        let textures = self.read_textures();
+        for (texture_index, offset) in textures.offsets.iter().enumerate() {
+            // Negative offsets mark missing textures
+            if *offset < 0 {
+                continue;
+            }
+            let texture_out_of_bounds = BspLumpError::TextureOutOfBounds {
+                texture_index,
+                offset: *offset,
+            };
+            let data = textures
+                .get_raw_data(texture_index)
+                .ok_or(texture_out_of_bounds)?;
+            if data.len() < size_of::<BspMipTextureHeader>() {
+                return Err(texture_out_of_bounds);
+            }
+            let texture = textures.get(texture_index).ok_or(texture_out_of_bounds)?;
+            if null_terminated_bytes_to_str(&data[..16]).is_err() {
+                return Err(BspLumpError::BadTextureName { texture_index });
+            }
+            if texture.has_local_image_data() {
+                for mip_level in 0..BspMipTextureReader::MIP_LEVELS.len() {
+                    if texture.get_image(mip_level).is_none() {
+                        return Err(BspLumpError::MipOutOfBounds {
+                            texture_index,
+                            mip_level,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn read_lump_raw(&self, index: usize) -> &[u8] {
+        // Lump bounds are checked by validate_lump when the reader is created
         let lump_header = self.header.lumps[index];
+        let len = lump_header.len as usize;
+        if let Some(words) = &self.aligned_lumps[index] {
+            return unsafe { std::slice::from_raw_parts(words.as_ptr() as *const u8, len) };
+        }
         let start = lump_header.offset as usize;
-        let end = start + lump_header.len as usize;
-        let lump_data = &self.data[start..end];
-        lump_data
+        &self.data[start..start + len]
+    }
+
+    /// Returns true if the lump had to be copied because it wasn't aligned in the
+    /// source data.
+    pub fn is_lump_copied(&self, index: usize) -> bool {
+        self.aligned_lumps[index].is_some()
     }
 
     fn read_lump<T: Sized>(&self, index: usize) -> &[T] {
+        // Size is checked by validate_lump and alignment by align_lumps when the
+        // reader is created
         let lump_data = &self.read_lump_raw(index);
+        // Empty lumps aren't aligned, even an empty slice needs an aligned pointer
+        if lump_data.is_empty() {
+            return &[];
+        }
         let len = lump_data.len() / std::mem::size_of::<T>();
         unsafe {
             let ptr = lump_data.as_ptr() as *const T;
@@ -311,6 +768,10 @@ impl BspLeaf {
     pub fn contents(&self) -> BspContents {
         BspContents::from_value(self.contents).unwrap()
     }
+
+    pub fn try_contents(&self) -> Option<BspContents> {
+        BspContents::from_value(self.contents)
+    }
 }
 
 impl BspVertex {
@@ -319,25 +780,63 @@ impl BspVertex {
     }
 }
 
+impl BspPlane {
+    /// Returns the signed distance from the plane to a point.
+    pub fn distance_to(&self, point: [f32; 3]) -> f32 {
+        match self.ty {
+            0..=2 => point[self.ty as usize] - self.dist,
+            _ => {
+                self.normal[0] * point[0] + self.normal[1] * point[1] + self.normal[2] * point[2]
+                    - self.dist
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspNodeChild {
+    Node(usize),
+    Leaf(usize),
+}
+
+impl BspNode {
+    /// Returns the front (0) or back (1) child of the node.
+    pub fn child(&self, side: usize) -> BspNodeChild {
+        let child = self.children[side];
+        if child >= 0 {
+            BspNodeChild::Node(child as usize)
+        } else {
+            BspNodeChild::Leaf(!child as usize)
+        }
+    }
+}
+
 pub struct BspTextureReader<'a> {
     offsets: &'a [i32],
     lump_data: &'a [u8],
+    has_palettes: bool,
 }
 
 impl<'a> BspTextureReader<'a> {
-    fn new(offsets: &'a [i32], lump_data: &'a [u8]) -> Self {
-        Self { offsets, lump_data }
+    fn new(offsets: &'a [i32], lump_data: &'a [u8], has_palettes: bool) -> Self {
+        Self {
+            offsets,
+            lump_data,
+            has_palettes,
+        }
     }
 
     pub fn len(&self) -> usize {
         self.offsets.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
     pub fn get(&self, index: usize) -> Option<BspMipTextureReader<'a>> {
         let data = self.get_raw_data(index)?;
-        let header_ptr = data.as_ptr() as *const BspMipTextureHeader;
-        let header = unsafe { header_ptr.as_ref() }?;
-        Some(BspMipTextureReader::new(header, data))
+        BspMipTextureReader::from_bytes(data, self.has_palettes)
     }
 
     fn get_raw_data(&self, index: usize) -> Option<&'a [u8]> {
@@ -346,10 +845,18 @@ impl<'a> BspTextureReader<'a> {
             None
         } else {
             let offset = offset_i32 as usize;
-            let end = *self
+            if offset > self.lump_data.len() {
+                return None;
+            }
+            // A texture extends up to the next texture in the lump, or the end of the lump
+            let end = self
                 .offsets
-                .get(index + 1)
-                .unwrap_or(&(self.lump_data.len() as i32)) as usize;
+                .iter()
+                .filter(|x| **x >= 0 && **x as usize > offset)
+                .map(|x| *x as usize)
+                .min()
+                .unwrap_or(self.lump_data.len())
+                .min(self.lump_data.len());
             let data = &self.lump_data[offset..end];
             Some(data)
         }
@@ -357,27 +864,45 @@ impl<'a> BspTextureReader<'a> {
 }
 
 pub struct BspMipTextureReader<'a> {
-    header: &'a BspMipTextureHeader,
+    header: BspMipTextureHeader,
     data: &'a [u8],
+    has_palette: bool,
 }
 
 impl<'a> BspMipTextureReader<'a> {
     const MIP_LEVELS: [usize; 4] = [1, 2, 4, 8];
 
-    fn new(header: &'a BspMipTextureHeader, data: &'a [u8]) -> Self {
-        Self { header, data }
+    fn new(header: BspMipTextureHeader, data: &'a [u8], has_palette: bool) -> Self {
+        Self {
+            header,
+            data,
+            has_palette,
+        }
+    }
+
+    /// Reads miptex data, which is laid out the same in maps and wads. Returns
+    /// `None` if the data is too short for the header.
+    pub(crate) fn from_bytes(data: &'a [u8], has_palette: bool) -> Option<Self> {
+        if data.len() < std::mem::size_of::<BspMipTextureHeader>() {
+            return None;
+        }
+        // Textures aren't guaranteed to be aligned within the lump
+        let header =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const BspMipTextureHeader) };
+        Some(Self::new(header, data, has_palette))
     }
 
     pub fn raw_data(&self) -> &[u8] {
-        &self.data
+        self.data
     }
 
     pub fn header(&self) -> &BspMipTextureHeader {
-        self.header
+        &self.header
     }
 
     pub fn get_image_name(&self) -> &'a str {
-        null_terminated_bytes_to_str(&self.header.name).unwrap()
+        // Names were checked to be UTF-8 by validate_textures
+        null_terminated_bytes_to_str(&self.data[..16]).unwrap_or_default()
     }
 
     pub fn has_local_image_data(&self) -> bool {
@@ -393,25 +918,68 @@ impl<'a> BspMipTextureReader<'a> {
         let mip_level = Self::MIP_LEVELS.get(index)?;
         let width = self.header.width as usize / mip_level;
         let height = self.header.height as usize / mip_level;
-        let len = width * height;
-        Some(BspBitmap::new(
-            width,
-            height,
-            &self.data[offset..offset + len],
-        ))
+        let len = width.checked_mul(height)?;
+        let end = offset.checked_add(len)?;
+        Some(BspBitmap::new(width, height, self.data.get(offset..end)?))
     }
 
-    pub fn read_palette(&self) -> BspPaletteReader<'a> {
+    /// Returns the palette stored after the last mip level, or `None` if the
+    /// texture has no local image data, the palette is truncated, or the map is
+    /// a Quake map that uses a global palette instead.
+    pub fn read_palette(&self) -> Option<BspPaletteReader<'a>> {
+        if !self.has_local_image_data() || !self.has_palette {
+            return None;
+        }
         let last_image_offset = self.header.offsets[3] as usize;
         let mip_level = Self::MIP_LEVELS[3];
         let width = self.header.width as usize / mip_level;
         let height = self.header.height as usize / mip_level;
         let image_len = width * height;
 
-        let palette_offset = last_image_offset + image_len + 2;
-        let palette_len = 256 * 3;
+        let count_offset = last_image_offset + image_len;
+        let count_data = self.data.get(count_offset..count_offset + 2)?;
+        let num_colors = u16::from_le_bytes([count_data[0], count_data[1]]) as usize;
 
-        BspPaletteReader::new(&self.data[palette_offset..palette_offset + palette_len])
+        let palette_offset = count_offset + 2;
+        let palette_len = num_colors * 3;
+
+        Some(BspPaletteReader::new(
+            self.data
+                .get(palette_offset..palette_offset + palette_len)?,
+        ))
+    }
+
+    /// Masked textures (names starting with '{') use palette index 255 and pure
+    /// blue as their transparent color.
+    pub fn is_masked(&self) -> bool {
+        self.header.name[0] == b'{'
+    }
+
+    /// Decodes all four mip levels to RGBA. Returns `None` if the texture has no
+    /// local image data or palette.
+    pub fn decode(&self) -> Option<MipmapedTextureData> {
+        let palette = self.read_palette()?;
+        self.decode_with_palette(&palette)
+    }
+
+    /// Decodes all four mip levels with a palette from elsewhere, e.g. Quake's
+    /// gfx/palette.lmp (768 bytes of RGB).
+    pub fn decode_with_palette(&self, palette: &BspPaletteReader) -> Option<MipmapedTextureData> {
+        let masked = self.is_masked();
+        let mut images = Vec::with_capacity(Self::MIP_LEVELS.len());
+        for i in 0..Self::MIP_LEVELS.len() {
+            images.push(self.get_image(i)?.decode_rgba(palette, masked));
+        }
+        let mut images = images.into_iter();
+
+        Some(MipmapedTextureData {
+            image_width: self.header.width,
+            image_height: self.header.height,
+            image: images.next()?,
+            mipmap1: images.next()?,
+            mipmap2: images.next()?,
+            mipmap3: images.next()?,
+        })
     }
 }
 
@@ -420,10 +988,22 @@ pub struct BspPaletteReader<'a> {
 }
 
 impl<'a> BspPaletteReader<'a> {
-    fn new(data: &'a [u8]) -> Self {
+    pub fn new(data: &'a [u8]) -> Self {
         Self { data }
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.len() < 3
+    }
+
+    pub fn raw_data(&self) -> &'a [u8] {
+        self.data
+    }
+
     pub fn get(&self, index: usize) -> BspPixel {
         let offset = index * 3;
         let data = &self.data[offset..offset + 3];
@@ -433,8 +1013,18 @@ impl<'a> BspPaletteReader<'a> {
             b: data[2],
         }
     }
+
+    /// Like `get`, but returns black for indices past the end of the palette.
+    pub fn get_or_black(&self, index: usize) -> BspPixel {
+        if index < self.len() {
+            self.get(index)
+        } else {
+            BspPixel { r: 0, g: 0, b: 0 }
+        }
+    }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct BspPixel {
     pub r: u8,
     pub g: u8,
@@ -442,50 +1032,65 @@ pub struct BspPixel {
 }
 
 pub struct BspBitmap<'a> {
-    _width: usize,
-    _height: usize,
-    _data: &'a [u8],
+    width: usize,
+    height: usize,
+    data: &'a [u8],
 }
 
 impl<'a> BspBitmap<'a> {
+    const TRANSPARENT_INDEX: u8 = 255;
+
     fn new(width: usize, height: usize, data: &'a [u8]) -> Self {
         Self {
-            _width: width,
-            _height: height,
-            _data: data,
+            width,
+            height,
+            data,
         }
     }
 
-    pub fn decode(&self, _palette_reader: &BspPaletteReader<'a>) -> Vec<BspPixel> {
-        todo!()
+    pub fn width(&self) -> usize {
+        self.width
     }
-}
 
-#[derive(Debug)]
-pub struct BspEntity<'a>(pub HashMap<&'a str, &'a str>);
-
-impl<'a> BspEntity<'a> {
-    pub fn parse_entities(source: &'a str) -> Vec<BspEntity<'a>> {
-        let mut entities = Vec::new();
-        let mut current_entity = None;
-        for line in source.lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-            if line == "{" {
-                current_entity = Some(BspEntity(HashMap::new()));
-            } else if line == "}" {
-                let entity = current_entity.take().unwrap();
-                entities.push(entity);
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The palette indices of the image.
+    pub fn raw_data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn decode(&self, palette_reader: &BspPaletteReader<'a>) -> Vec<BspPixel> {
+        self.data
+            .iter()
+            .map(|index| palette_reader.get_or_black(*index as usize))
+            .collect()
+    }
+
+    pub fn decode_rgba(
+        &self,
+        palette_reader: &BspPaletteReader<'a>,
+        masked: bool,
+    ) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+        let mut image_rgba_data = Vec::with_capacity(self.data.len() * 4);
+        for palette_index in self.data {
+            let color = palette_reader.get_or_black(*palette_index as usize);
+            let is_transparent = masked
+                && (*palette_index == Self::TRANSPARENT_INDEX
+                    || (color.r == 0 && color.g == 0 && color.b == 255));
+            if is_transparent {
+                image_rgba_data.extend_from_slice(&[0, 0, 0, 0]);
             } else {
-                let entity = current_entity.as_mut().unwrap();
-                let mut split = line.split("\" \"");
-                let key = split.next().unwrap().trim_matches('\"');
-                let value = split.next().unwrap().trim_matches('\"');
-                entity.0.insert(&key, value);
+                image_rgba_data.extend_from_slice(&[color.r, color.g, color.b, 255]);
             }
         }
-        entities
+
+        image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_vec(
+            self.width as u32,
+            self.height as u32,
+            image_rgba_data,
+        )
+        .unwrap()
     }
 }
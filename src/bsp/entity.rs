@@ -0,0 +1,482 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// A single entity from the entity lump. Key/value pairs keep the order they
+/// appear in the lump, and duplicate keys are allowed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BspEntity<'a> {
+    pub pairs: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BspEntityParseErrorKind {
+    UnexpectedEof,
+    UnterminatedString,
+    ExpectedOpenBrace(String),
+    UnexpectedOpenBrace,
+    MissingValue(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BspEntityParseError {
+    /// 1-based line of the offending token.
+    pub line: usize,
+    /// 1-based column (in characters) of the offending token.
+    pub column: usize,
+    pub kind: BspEntityParseErrorKind,
+}
+
+impl std::fmt::Display for BspEntityParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            BspEntityParseErrorKind::UnexpectedEof => write!(f, "unexpected end of entity lump"),
+            BspEntityParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            BspEntityParseErrorKind::ExpectedOpenBrace(token) => {
+                write!(f, "expected '{{' but found \"{}\"", token)
+            }
+            BspEntityParseErrorKind::UnexpectedOpenBrace => {
+                write!(f, "unexpected '{{' inside an entity")
+            }
+            BspEntityParseErrorKind::MissingValue(key) => {
+                write!(f, "key \"{}\" has no value", key)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BspEntityParseError {}
+
+/// A key or value that can't be written to the entity lump. There's no
+/// escaping, so quotes would end the string early and line breaks would split
+/// the pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BspEntitySerializeError {
+    pub key: String,
+    pub value: String,
+}
+
+impl std::fmt::Display for BspEntitySerializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "key {:?} with value {:?} contains a quote or line break",
+            self.key, self.value
+        )
+    }
+}
+
+impl std::error::Error for BspEntitySerializeError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    OpenBrace,
+    CloseBrace,
+    String,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+struct Tokenizer<'a> {
+    source: &'a str,
+    position: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.position += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match self.peek_char() {
+                // Null bytes can pad the end of the lump
+                Some(c) if c.is_whitespace() || c == '\0' => {
+                    self.next_char();
+                }
+                Some('/') if self.source[self.position..].starts_with("//") => {
+                    while let Some(c) = self.next_char() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, BspEntityParseError> {
+        self.skip_whitespace_and_comments();
+        let line = self.line;
+        let column = self.column;
+        let start = self.position;
+        let Some(c) = self.next_char() else {
+            return Ok(None);
+        };
+
+        let token = |kind, text| Token {
+            kind,
+            text,
+            line,
+            column,
+        };
+        match c {
+            '{' => Ok(Some(token(TokenKind::OpenBrace, "{"))),
+            '}' => Ok(Some(token(TokenKind::CloseBrace, "}"))),
+            '"' => {
+                // Strings can't contain quotes, there's no escaping
+                let text_start = self.position;
+                loop {
+                    match self.next_char() {
+                        Some('"') => break,
+                        Some(_) => {}
+                        None => {
+                            return Err(BspEntityParseError {
+                                line,
+                                column,
+                                kind: BspEntityParseErrorKind::UnterminatedString,
+                            });
+                        }
+                    }
+                }
+                let text = &self.source[text_start..self.position - 1];
+                Ok(Some(token(TokenKind::String, text)))
+            }
+            _ => {
+                // Unquoted words run until whitespace, a brace or a quote
+                while let Some(c) = self.peek_char() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == '"' || c == '\0' {
+                        break;
+                    }
+                    self.next_char();
+                }
+                let text = &self.source[start..self.position];
+                Ok(Some(token(TokenKind::String, text)))
+            }
+        }
+    }
+}
+
+impl<'a> BspEntity<'a> {
+    pub fn new() -> Self {
+        Self { pairs: Vec::new() }
+    }
+
+    /// Parses the text of an entity lump.
+    pub fn parse_entities(source: &'a str) -> Result<Vec<BspEntity<'a>>, BspEntityParseError> {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut entities = Vec::new();
+        while let Some(token) = tokenizer.next_token()? {
+            if token.kind != TokenKind::OpenBrace {
+                return Err(BspEntityParseError {
+                    line: token.line,
+                    column: token.column,
+                    kind: BspEntityParseErrorKind::ExpectedOpenBrace(token.text.to_owned()),
+                });
+            }
+
+            let mut entity = BspEntity::new();
+            loop {
+                let eof = BspEntityParseError {
+                    line: tokenizer.line,
+                    column: tokenizer.column,
+                    kind: BspEntityParseErrorKind::UnexpectedEof,
+                };
+                let key = tokenizer.next_token()?.ok_or(eof)?;
+                match key.kind {
+                    TokenKind::CloseBrace => break,
+                    TokenKind::OpenBrace => {
+                        return Err(BspEntityParseError {
+                            line: key.line,
+                            column: key.column,
+                            kind: BspEntityParseErrorKind::UnexpectedOpenBrace,
+                        });
+                    }
+                    TokenKind::String => {}
+                }
+
+                let missing_value = BspEntityParseError {
+                    line: key.line,
+                    column: key.column,
+                    kind: BspEntityParseErrorKind::MissingValue(key.text.to_owned()),
+                };
+                let value = tokenizer.next_token()?.ok_or(missing_value.clone())?;
+                if value.kind != TokenKind::String {
+                    return Err(missing_value);
+                }
+                entity.push(key.text, value.text);
+            }
+            entities.push(entity);
+        }
+        Ok(entities)
+    }
+
+    /// Writes entities back out as lump text, the same way the compilers do.
+    /// The null terminator isn't included.
+    pub fn serialize_entities(entities: &[BspEntity]) -> Result<String, BspEntitySerializeError> {
+        let mut output = String::new();
+        for entity in entities {
+            entity.serialize(&mut output)?;
+        }
+        Ok(output)
+    }
+
+    /// Appends the entity as lump text. Nothing is written if a key or value
+    /// can't be stored.
+    pub fn serialize(&self, output: &mut String) -> Result<(), BspEntitySerializeError> {
+        let is_invalid = |x: &str| x.contains(['"', '\n', '\r']);
+        if let Some((key, value)) = self
+            .pairs
+            .iter()
+            .find(|(key, value)| is_invalid(key) || is_invalid(value))
+        {
+            return Err(BspEntitySerializeError {
+                key: key.to_string(),
+                value: value.to_string(),
+            });
+        }
+
+        output.push_str("{\n");
+        for (key, value) in &self.pairs {
+            writeln!(output, "\"{}\" \"{}\"", key, value).unwrap();
+        }
+        output.push_str("}\n");
+        Ok(())
+    }
+
+    /// Returns the value of a key. If the key appears more than once, the last
+    /// value wins, which matches how the game applies them.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .rev()
+            .find(|(x, _)| x == key)
+            .map(|(_, value)| value.as_ref())
+    }
+
+    /// Returns every value of a key, in order.
+    pub fn get_all<'b>(&'b self, key: &'b str) -> impl Iterator<Item = &'b str> + 'b {
+        self.pairs
+            .iter()
+            .filter(move |(x, _)| x == key)
+            .map(|(_, value)| value.as_ref())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(x, _)| x == key)
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    /// Parses a value made of three numbers, like "origin" or "angles".
+    pub fn get_vector(&self, key: &str) -> Option<[f32; 3]> {
+        let mut values = self.get(key)?.split_whitespace().map(|x| x.parse::<f32>());
+        let x = values.next()?.ok()?;
+        let y = values.next()?.ok()?;
+        let z = values.next()?.ok()?;
+        Some([x, y, z])
+    }
+
+    /// Returns the index of the brush model referenced by the "model" key, e.g. 3
+    /// for "*3".
+    pub fn get_model_index(&self) -> Option<usize> {
+        self.get("model")?.strip_prefix('*')?.parse().ok()
+    }
+
+    pub fn push<K: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>>(&mut self, key: K, value: V) {
+        self.pairs.push((key.into(), value.into()));
+    }
+
+    /// Replaces the value of every pair with this key, or adds a new pair if
+    /// the key isn't present.
+    pub fn set<K: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        let value = value.into();
+        let mut found = false;
+        for (x, old_value) in &mut self.pairs {
+            if *x == key {
+                *old_value = value.clone();
+                found = true;
+            }
+        }
+        if !found {
+            self.pairs.push((key, value));
+        }
+    }
+
+    /// Removes every pair with this key, returning how many were removed.
+    pub fn remove(&mut self, key: &str) -> usize {
+        let len = self.pairs.len();
+        self.pairs.retain(|(x, _)| x != key);
+        len - self.pairs.len()
+    }
+
+    pub fn into_owned(self) -> BspEntity<'static> {
+        BspEntity {
+            pairs: self
+                .pairs
+                .into_iter()
+                .map(|(key, value)| (Cow::Owned(key.into_owned()), Cow::Owned(value.into_owned())))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(source: &str) -> (usize, usize, BspEntityParseErrorKind) {
+        let error = BspEntity::parse_entities(source).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn quoted_and_unquoted_tokens() {
+        let source =
+            "{\n\"classname\" \"worldspawn\"\nwad halflife.wad\n\"message\" \"two words\"\n}\n";
+        let entities = BspEntity::parse_entities(source).unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].classname(), Some("worldspawn"));
+        assert_eq!(entities[0].get("wad"), Some("halflife.wad"));
+        assert_eq!(entities[0].get("message"), Some("two words"));
+    }
+
+    #[test]
+    fn unquoted_tokens_stop_at_braces_and_quotes() {
+        let entities = BspEntity::parse_entities("{key\"value\"}{a b}").unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].get("key"), Some("value"));
+        assert_eq!(entities[1].get("a"), Some("b"));
+    }
+
+    #[test]
+    fn comments_and_padding() {
+        let source = "// comment\n{\n\"a\" \"b\" // trailing\n}\n\0\0";
+        let entities = BspEntity::parse_entities(source).unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].pairs.len(), 1);
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let source = "{\n\"target\" \"a\"\n\"target\" \"b\"\n}\n";
+        let mut entities = BspEntity::parse_entities(source).unwrap();
+        let entity = &mut entities[0];
+        assert_eq!(entity.pairs.len(), 2);
+        assert_eq!(entity.get("target"), Some("b"));
+        assert_eq!(entity.get_all("target").collect::<Vec<_>>(), ["a", "b"]);
+
+        entity.set("target", "c");
+        assert_eq!(entity.get_all("target").collect::<Vec<_>>(), ["c", "c"]);
+        assert_eq!(entity.remove("target"), 2);
+        assert!(!entity.contains_key("target"));
+    }
+
+    #[test]
+    fn unterminated_brace() {
+        assert_eq!(
+            parse_error("{\n\"a\" \"b\"\n"),
+            (2, 8, BspEntityParseErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        assert_eq!(
+            parse_error("{\n\"a\" \"b\"\n\"c\" \"d\n}\n"),
+            (3, 5, BspEntityParseErrorKind::UnterminatedString)
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(
+            parse_error("{\n\"a\" \"b\"\n  \"c\" }\n"),
+            (3, 3, BspEntityParseErrorKind::MissingValue("c".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unexpected_braces() {
+        assert_eq!(
+            parse_error("{\n\"a\" \"b\"\n{\n"),
+            (3, 1, BspEntityParseErrorKind::UnexpectedOpenBrace)
+        );
+        assert_eq!(
+            parse_error("{\n}\n\"a\"\n"),
+            (
+                3,
+                1,
+                BspEntityParseErrorKind::ExpectedOpenBrace("a".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let source = "{\nclassname worldspawn\n\"wad\" \"\"\n}\n{\n\"classname\" \"light\"\n\"origin\" \"0 0 64\"\n\"_light\" \"255 255 255 200\"\n\"_light\" \"duplicate\"\n}\n";
+        let entities = BspEntity::parse_entities(source).unwrap();
+        let serialized = BspEntity::serialize_entities(&entities).unwrap();
+        let reparsed = BspEntity::parse_entities(&serialized).unwrap();
+        assert_eq!(entities, reparsed);
+        assert_eq!(
+            BspEntity::serialize_entities(&reparsed).unwrap(),
+            serialized
+        );
+    }
+
+    #[test]
+    fn serialize_invalid_values() {
+        for (key, value) in [("a\"b", "c"), ("a", "b\"c"), ("message", "two\nlines")] {
+            let mut entity = BspEntity::new();
+            entity.push("classname", "info_null");
+            entity.push(key, value);
+            let mut output = String::new();
+            assert_eq!(
+                entity.serialize(&mut output),
+                Err(BspEntitySerializeError {
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                })
+            );
+            assert!(output.is_empty());
+        }
+
+        // Quoted strings in the lump can span lines, but can't be written back
+        let entities = BspEntity::parse_entities("{\n\"message\" \"two\nlines\"\n}\n").unwrap();
+        assert!(BspEntity::serialize_entities(&entities).is_err());
+    }
+}
@@ -0,0 +1,524 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::util::resolve_map_entity_string;
+
+use super::{BspEntity, BspEntityParseError, BspReader};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BspEntityLinkKind {
+    /// "target", fired when the entity triggers.
+    Target,
+    /// "killtarget", removed when the entity triggers.
+    KillTarget,
+    /// A multi_manager key, fired after the key's value in seconds.
+    MultiManager,
+    /// "master", must be active for the entity to work.
+    Master,
+    /// "netname", fired by entities like path_track on a dead end.
+    NetName,
+    /// "changetarget" on trigger_changelevel, fired after the level change.
+    ChangeTarget,
+    /// "m_iszNewTarget" on trigger_changetarget, the new target to assign.
+    NewTarget,
+    /// "landmark" on trigger_changelevel.
+    Landmark,
+}
+
+impl BspEntityLinkKind {
+    /// Returns true if following the link fires the entities it points to.
+    pub fn fires(&self) -> bool {
+        matches!(
+            self,
+            BspEntityLinkKind::Target
+                | BspEntityLinkKind::MultiManager
+                | BspEntityLinkKind::NetName
+                | BspEntityLinkKind::ChangeTarget
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BspEntityLink {
+    pub source: usize,
+    pub kind: BspEntityLinkKind,
+    pub name: String,
+    /// Seconds between the source triggering and the link firing.
+    pub delay: f32,
+    /// Every entity with a targetname matching `name`.
+    pub targets: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BspEntityNode {
+    pub classname: String,
+    pub targetname: Option<String>,
+}
+
+/// A target fired as part of a chain, see `BspEntityGraph::get_fire_chain`.
+#[derive(Clone, Debug)]
+pub struct BspFireEvent {
+    pub entity: usize,
+    /// Seconds after the chain started.
+    pub time: f32,
+    /// The link that fired the entity.
+    pub link: usize,
+    /// How many links away from the start of the chain the entity is.
+    pub depth: usize,
+}
+
+/// How entities trigger each other through targetnames.
+#[derive(Clone, Debug)]
+pub struct BspEntityGraph {
+    pub entities: Vec<BspEntityNode>,
+    pub links: Vec<BspEntityLink>,
+    names: HashMap<String, Vec<usize>>,
+    outgoing: Vec<Vec<usize>>,
+    incoming: Vec<Vec<usize>>,
+}
+
+// Entities that only do something when another entity fires them
+const FIRED_ONLY_CLASSNAMES: &[&str] = &[
+    "multi_manager",
+    "multisource",
+    "trigger_relay",
+    "trigger_changetarget",
+    "trigger_counter",
+    "trigger_camera",
+    "ambient_generic",
+    "env_message",
+    "env_shake",
+    "env_fade",
+    "env_explosion",
+    "env_render",
+    "game_text",
+    "game_end",
+    "scripted_sentence",
+];
+
+// Every entvars field, from gEntvarsDescription in the Half-Life SDK. The game
+// stores these on the entity before calling its KeyValue, so multi_manager never
+// sees them as targets.
+const ENTVARS_KEYS: [&str; 86] = [
+    "classname",
+    "globalname",
+    "origin",
+    "oldorigin",
+    "velocity",
+    "basevelocity",
+    "movedir",
+    "angles",
+    "avelocity",
+    "punchangle",
+    "v_angle",
+    "fixangle",
+    "idealpitch",
+    "pitch_speed",
+    "ideal_yaw",
+    "yaw_speed",
+    "modelindex",
+    "model",
+    "viewmodel",
+    "weaponmodel",
+    "absmin",
+    "absmax",
+    "mins",
+    "maxs",
+    "size",
+    "ltime",
+    "nextthink",
+    "solid",
+    "movetype",
+    "skin",
+    "body",
+    "effects",
+    "gravity",
+    "friction",
+    "light_level",
+    "frame",
+    "scale",
+    "sequence",
+    "animtime",
+    "framerate",
+    "controller",
+    "blending",
+    "rendermode",
+    "renderamt",
+    "rendercolor",
+    "renderfx",
+    "health",
+    "frags",
+    "weapons",
+    "takedamage",
+    "deadflag",
+    "view_ofs",
+    "button",
+    "impulse",
+    "chain",
+    "dmg_inflictor",
+    "enemy",
+    "aiment",
+    "owner",
+    "groundentity",
+    "spawnflags",
+    "flags",
+    "colormap",
+    "team",
+    "max_health",
+    "teleport_time",
+    "armortype",
+    "armorvalue",
+    "waterlevel",
+    "watertype",
+    "target",
+    "targetname",
+    "netname",
+    "message",
+    "dmg_take",
+    "dmg_save",
+    "dmg",
+    "dmgtime",
+    "noise",
+    "noise1",
+    "noise2",
+    "noise3",
+    "speed",
+    "air_finished",
+    "pain_finished",
+    "radsuit_finished",
+];
+
+// Keys multi_manager doesn't treat as targets besides the entvars fields: "angle"
+// is turned into "angles" by the engine and "wait" is read by multi_manager. The
+// game would take the others as targets, but they're reported through their own
+// links instead.
+const MULTI_MANAGER_RESERVED_KEYS: [&str; 5] = ["angle", "wait", "delay", "killtarget", "master"];
+
+// An entity waiting to fire in get_fire_chain. Ordered so the earliest, then
+// closest, pops first from the max heap.
+struct QueuedFire {
+    time: f32,
+    depth: usize,
+    entity: usize,
+    link: Option<usize>,
+}
+
+impl Ord for QueuedFire {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .time
+            .total_cmp(&self.time)
+            .then(other.depth.cmp(&self.depth))
+            .then(other.entity.cmp(&self.entity))
+    }
+}
+
+impl PartialOrd for QueuedFire {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedFire {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedFire {}
+
+impl BspEntityGraph {
+    pub fn new(entities: &[BspEntity]) -> Self {
+        let mut nodes = Vec::with_capacity(entities.len());
+        let mut names: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entity) in entities.iter().enumerate() {
+            let classname = entity.classname().unwrap_or_default();
+            let targetname = entity.get("targetname").filter(|x| !x.is_empty());
+            if let Some(targetname) = targetname {
+                names.entry(targetname.to_owned()).or_default().push(i);
+            }
+            // Monsters spawned by a monstermaker are named with its netname
+            if classname == "monstermaker"
+                && let Some(netname) = entity.get("netname").filter(|x| !x.is_empty())
+            {
+                names.entry(netname.to_owned()).or_default().push(i);
+            }
+            nodes.push(BspEntityNode {
+                classname: classname.to_owned(),
+                targetname: targetname.map(|x| x.to_owned()),
+            });
+        }
+
+        let mut links = Vec::new();
+        for (source, entity) in entities.iter().enumerate() {
+            let classname = entity.classname().unwrap_or_default();
+            let delay = parse_delay(entity.get("delay"));
+            let mut add_link = |kind, name: &str, delay| {
+                if name.is_empty() {
+                    return;
+                }
+                links.push(BspEntityLink {
+                    source,
+                    kind,
+                    name: name.to_owned(),
+                    delay,
+                    targets: names.get(name).cloned().unwrap_or_default(),
+                });
+            };
+
+            if classname == "multi_manager" {
+                for (key, value) in &entity.pairs {
+                    // Entvars fields are matched regardless of case
+                    let is_reserved = ENTVARS_KEYS
+                        .iter()
+                        .chain(MULTI_MANAGER_RESERVED_KEYS.iter())
+                        .any(|x| x.eq_ignore_ascii_case(key));
+                    if is_reserved {
+                        continue;
+                    }
+                    // Duplicate targets are written as "name#1", "name#2", ...
+                    let name = key.split('#').next().unwrap_or_default();
+                    add_link(
+                        BspEntityLinkKind::MultiManager,
+                        name,
+                        parse_delay(Some(value)),
+                    );
+                }
+            }
+            if let Some(target) = entity.get("target") {
+                add_link(BspEntityLinkKind::Target, target, delay);
+            }
+            if let Some(killtarget) = entity.get("killtarget") {
+                add_link(BspEntityLinkKind::KillTarget, killtarget, delay);
+            }
+            if let Some(master) = entity.get("master") {
+                add_link(BspEntityLinkKind::Master, master, 0.0);
+            }
+            if classname != "monstermaker"
+                && let Some(netname) = entity.get("netname")
+            {
+                add_link(BspEntityLinkKind::NetName, netname, delay);
+            }
+            if let Some(changetarget) = entity.get("changetarget") {
+                add_link(BspEntityLinkKind::ChangeTarget, changetarget, 0.0);
+            }
+            if let Some(new_target) = entity.get("m_iszNewTarget") {
+                add_link(BspEntityLinkKind::NewTarget, new_target, 0.0);
+            }
+            if let Some(landmark) = entity.get("landmark") {
+                add_link(BspEntityLinkKind::Landmark, landmark, 0.0);
+            }
+        }
+
+        let mut outgoing = vec![Vec::new(); nodes.len()];
+        let mut incoming = vec![Vec::new(); nodes.len()];
+        for (i, link) in links.iter().enumerate() {
+            outgoing[link.source].push(i);
+            for target in &link.targets {
+                incoming[*target].push(i);
+            }
+        }
+
+        Self {
+            entities: nodes,
+            links,
+            names,
+            outgoing,
+            incoming,
+        }
+    }
+
+    pub fn from_reader(reader: &BspReader) -> Result<Self, BspEntityParseError> {
+        let entity_string = resolve_map_entity_string(reader);
+        let entities = BspEntity::parse_entities(&entity_string)?;
+        Ok(Self::new(&entities))
+    }
+
+    /// Returns every entity with the given targetname.
+    pub fn get_entities_named(&self, name: &str) -> &[usize] {
+        self.names.get(name).map(|x| x.as_slice()).unwrap_or(&[])
+    }
+
+    /// Returns the links from an entity to others.
+    pub fn get_outgoing(&self, entity: usize) -> impl Iterator<Item = &BspEntityLink> {
+        self.outgoing
+            .get(entity)
+            .into_iter()
+            .flatten()
+            .map(|x| &self.links[*x])
+    }
+
+    /// Returns the links from other entities to this one.
+    pub fn get_incoming(&self, entity: usize) -> impl Iterator<Item = &BspEntityLink> {
+        self.incoming
+            .get(entity)
+            .into_iter()
+            .flatten()
+            .map(|x| &self.links[*x])
+    }
+
+    /// Returns links that name an entity that doesn't exist.
+    pub fn get_dangling_links(&self) -> Vec<&BspEntityLink> {
+        self.links.iter().filter(|x| x.targets.is_empty()).collect()
+    }
+
+    /// Returns entities that only act when fired, but that nothing fires.
+    pub fn get_unreachable_triggers(&self) -> Vec<usize> {
+        (0..self.entities.len())
+            .filter(|i| FIRED_ONLY_CLASSNAMES.contains(&self.entities[*i].classname.as_str()))
+            .filter(|i| !self.get_incoming(*i).any(|x| x.kind.fires()))
+            .collect()
+    }
+
+    /// Returns groups of entities that fire each other in a loop. Some loops
+    /// are intentional, such as a multi_manager that retriggers itself.
+    pub fn find_cycles(&self) -> Vec<Vec<usize>> {
+        // Tarjan's strongly connected components, iteratively
+        let len = self.entities.len();
+        let mut index = vec![usize::MAX; len];
+        let mut low_link = vec![0; len];
+        let mut on_stack = vec![false; len];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut cycles = Vec::new();
+
+        for root in 0..len {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            let mut work = vec![(root, self.fired_entities(root))];
+            index[root] = next_index;
+            low_link[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some((node, children)) = work.last_mut() {
+                let node = *node;
+                if let Some(child) = children.pop() {
+                    if index[child] == usize::MAX {
+                        index[child] = next_index;
+                        low_link[child] = next_index;
+                        next_index += 1;
+                        stack.push(child);
+                        on_stack[child] = true;
+                        work.push((child, self.fired_entities(child)));
+                    } else if on_stack[child] {
+                        low_link[node] = low_link[node].min(index[child]);
+                    }
+                    continue;
+                }
+
+                work.pop();
+                if let Some((parent, _)) = work.last() {
+                    low_link[*parent] = low_link[*parent].min(low_link[node]);
+                }
+                if low_link[node] == index[node] {
+                    let mut component = Vec::new();
+                    while let Some(x) = stack.pop() {
+                        on_stack[x] = false;
+                        component.push(x);
+                        if x == node {
+                            break;
+                        }
+                    }
+                    let is_cycle = component.len() > 1 || self.fired_entities(node).contains(&node);
+                    if is_cycle {
+                        component.sort();
+                        cycles.push(component);
+                    }
+                }
+            }
+        }
+        cycles
+    }
+
+    /// Returns everything fired, directly or indirectly, when an entity triggers
+    /// (e.g. a button is pressed), ordered by time. Each entity is only reported
+    /// the earliest time it fires.
+    pub fn get_fire_chain(&self, entity: usize) -> Vec<BspFireEvent> {
+        let mut events = Vec::new();
+        // Entities are settled when popped, since a longer chain of links can
+        // still fire an entity sooner than a shorter one
+        let mut settled = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(QueuedFire {
+            time: 0.0,
+            depth: 0,
+            entity,
+            link: None,
+        });
+        while let Some(fire) = queue.pop() {
+            if !settled.insert(fire.entity) {
+                continue;
+            }
+            if let Some(link) = fire.link {
+                events.push(BspFireEvent {
+                    entity: fire.entity,
+                    time: fire.time,
+                    link,
+                    depth: fire.depth,
+                });
+            }
+            for link_index in self.outgoing.get(fire.entity).into_iter().flatten() {
+                let link = &self.links[*link_index];
+                if !link.kind.fires() {
+                    continue;
+                }
+                for target in &link.targets {
+                    if settled.contains(target) {
+                        continue;
+                    }
+                    queue.push(QueuedFire {
+                        time: fire.time + link.delay,
+                        depth: fire.depth + 1,
+                        entity: *target,
+                        link: Some(*link_index),
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    fn fired_entities(&self, entity: usize) -> Vec<usize> {
+        self.get_outgoing(entity)
+            .filter(|x| x.kind.fires())
+            .flat_map(|x| x.targets.iter().copied())
+            .collect()
+    }
+}
+
+// The engine fires targets with a negative delay right away, and the fire chain
+// relies on delays never going back in time
+fn parse_delay(value: Option<&str>) -> f32 {
+    value
+        .and_then(|x| x.trim().parse::<f32>().ok())
+        .filter(|x| x.is_finite())
+        .unwrap_or(0.0)
+        .max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_delays() {
+        let entities = BspEntity::parse_entities(
+            "{\n\"classname\" \"trigger_relay\"\n\"target\" \"mm\"\n\"delay\" \"2\"\n}\n\
+             {\n\"classname\" \"multi_manager\"\n\"targetname\" \"mm\"\n\"door\" \"-5\"\n\"light\" \"nan\"\n}\n\
+             {\n\"classname\" \"func_door\"\n\"targetname\" \"door\"\n}\n\
+             {\n\"classname\" \"light\"\n\"targetname\" \"light\"\n}\n",
+        )
+        .unwrap();
+        let graph = BspEntityGraph::new(&entities);
+        let mut times: Vec<_> = graph
+            .get_fire_chain(0)
+            .iter()
+            .map(|x| (x.entity, x.time))
+            .collect();
+        times.sort_by_key(|x| x.0);
+        assert_eq!(times, [(1, 2.0), (2, 2.0), (3, 2.0)]);
+    }
+}
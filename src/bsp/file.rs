@@ -0,0 +1,442 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::{
+    BspClipNode, BspEdge, BspFace, BspLeaf, BspLumpHeader, BspMarkSurface, BspModel, BspNode,
+    BspPlane, BspReader, BspSurfaceEdge, BspTextureHeader, BspTextureInfo, BspTextureReader,
+    BspVariant, BspVertex, HEADER_LUMPS, LUMP_CLIPNODES, LUMP_EDGES, LUMP_ENTITIES, LUMP_FACES,
+    LUMP_LEAVES, LUMP_LIGHTING, LUMP_MARKSURFACES, LUMP_MODELS, LUMP_NODES, LUMP_PLANES,
+    LUMP_SURFEDGES, LUMP_TEXINFO, LUMP_TEXTURES, LUMP_VERTICES, LUMP_VISIBILITY,
+};
+
+// The order the compilers (hlbsp, ZHLT, VHLT) write lumps in
+pub const COMPILER_LUMP_ORDER: [usize; HEADER_LUMPS] = [
+    LUMP_PLANES,
+    LUMP_LEAVES,
+    LUMP_VERTICES,
+    LUMP_NODES,
+    LUMP_TEXINFO,
+    LUMP_FACES,
+    LUMP_CLIPNODES,
+    LUMP_MARKSURFACES,
+    LUMP_SURFEDGES,
+    LUMP_EDGES,
+    LUMP_MODELS,
+    LUMP_LIGHTING,
+    LUMP_VISIBILITY,
+    LUMP_ENTITIES,
+    LUMP_TEXTURES,
+];
+
+/// An owned, editable copy of every lump in a bsp file.
+#[derive(Clone, Debug)]
+pub struct BspFile {
+    pub variant: BspVariant,
+    pub entities: Vec<u8>,
+    pub planes: Vec<BspPlane>,
+    /// Raw miptex data for each texture, `None` for textures with a negative offset.
+    pub textures: Vec<Option<Vec<u8>>>,
+    pub vertices: Vec<BspVertex>,
+    pub visibility: Vec<u8>,
+    pub nodes: Vec<BspNode>,
+    pub texture_infos: Vec<BspTextureInfo>,
+    pub faces: Vec<BspFace>,
+    pub lighting: Vec<u8>,
+    pub clip_nodes: Vec<BspClipNode>,
+    pub leaves: Vec<BspLeaf>,
+    pub mark_surfaces: Vec<BspMarkSurface>,
+    pub edges: Vec<BspEdge>,
+    pub surface_edges: Vec<BspSurfaceEdge>,
+    pub models: Vec<BspModel>,
+    /// The order lumps are laid out in the file.
+    pub lump_order: [usize; HEADER_LUMPS],
+    source: Option<BspFileSource>,
+}
+
+// The file a `BspFile` was read from. Lumps that are written back unchanged keep
+// their place in it, so an unmodified map comes out byte for byte.
+#[derive(Clone, Debug)]
+struct BspFileSource {
+    data: Vec<u8>,
+    /// In the standard lump order.
+    lumps: [BspLumpHeader; HEADER_LUMPS],
+    lump_order: [usize; HEADER_LUMPS],
+}
+
+impl BspFile {
+    pub fn new() -> Self {
+        Self {
+            variant: BspVariant::GoldSrc,
+            entities: Vec::new(),
+            planes: Vec::new(),
+            textures: Vec::new(),
+            vertices: Vec::new(),
+            visibility: Vec::new(),
+            nodes: Vec::new(),
+            texture_infos: Vec::new(),
+            faces: Vec::new(),
+            lighting: Vec::new(),
+            clip_nodes: Vec::new(),
+            leaves: Vec::new(),
+            mark_surfaces: Vec::new(),
+            edges: Vec::new(),
+            surface_edges: Vec::new(),
+            models: Vec::new(),
+            lump_order: COMPILER_LUMP_ORDER,
+            source: None,
+        }
+    }
+
+    pub fn from_reader(reader: &BspReader) -> Self {
+        let textures = reader.read_textures();
+        let textures = (0..textures.len())
+            .map(|i| textures.get_raw_data(i).map(|x| x.to_vec()))
+            .collect();
+
+        // Keep the lumps in the same order as the source file so an unmodified
+        // map writes back out byte for byte.
+        let header = reader.header();
+        let mut lump_order = COMPILER_LUMP_ORDER;
+        lump_order.sort_by_key(|lump| header.lumps[*lump].offset);
+
+        Self {
+            variant: reader.variant(),
+            entities: reader.read_entities().to_vec(),
+            planes: reader.read_planes().to_vec(),
+            textures,
+            vertices: reader.read_vertices().to_vec(),
+            visibility: reader.read_visibility_data().to_vec(),
+            nodes: reader.read_nodes().to_vec(),
+            texture_infos: reader.read_texture_infos().to_vec(),
+            faces: reader.read_faces().to_vec(),
+            lighting: reader.read_lighting_data().to_vec(),
+            clip_nodes: reader.read_clip_nodes().to_vec(),
+            leaves: reader.read_leaves().to_vec(),
+            mark_surfaces: reader.read_mark_surfaces().to_vec(),
+            edges: reader.read_edges().to_vec(),
+            surface_edges: reader.read_surface_edges().to_vec(),
+            models: reader.read_models().to_vec(),
+            lump_order,
+            source: Some(BspFileSource {
+                data: reader.data.to_vec(),
+                lumps: header.lumps,
+                lump_order,
+            }),
+        }
+    }
+
+    pub fn set_entities_str(&mut self, entities: &str) {
+        self.entities = entities.as_bytes().to_vec();
+        self.entities.push(0);
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let bytes = self.to_bytes();
+        writer.write_all(&bytes)
+    }
+
+    /// Lumps that haven't changed since the file was read stay where they were,
+    /// as do changed lumps that still fit. The rest are appended to the end. A
+    /// file that wasn't read from a map, or whose `lump_order` was changed, is
+    /// laid out from scratch.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let lump_data = self.lump_data();
+        let (mut data, mut lumps) = match self
+            .source
+            .as_ref()
+            .filter(|x| x.lump_order == self.lump_order)
+        {
+            Some(source) => self.layout_in_source(source, &lump_data),
+            None => self.layout(&lump_data),
+        };
+
+        let header_size = std::mem::size_of::<super::BspHeader>();
+        let mut header = Vec::with_capacity(header_size);
+        self.variant.swap_lumps(&mut lumps);
+        header
+            .write_i32::<LittleEndian>(self.variant.version())
+            .unwrap();
+        for lump in lumps {
+            header.write_i32::<LittleEndian>(lump.offset).unwrap();
+            header.write_i32::<LittleEndian>(lump.len).unwrap();
+        }
+        data[..header_size].copy_from_slice(&header);
+        data
+    }
+
+    fn layout(&self, lump_data: &[Cow<[u8]>]) -> (Vec<u8>, [BspLumpHeader; HEADER_LUMPS]) {
+        let header_size = std::mem::size_of::<super::BspHeader>();
+        let mut lumps = [BspLumpHeader { offset: 0, len: 0 }; HEADER_LUMPS];
+        let mut data = vec![0u8; header_size];
+        for lump in self.lump_order {
+            lumps[lump] = append_lump(&mut data, &lump_data[lump]);
+        }
+        (data, lumps)
+    }
+
+    fn layout_in_source(
+        &self,
+        source: &BspFileSource,
+        lump_data: &[Cow<[u8]>],
+    ) -> (Vec<u8>, [BspLumpHeader; HEADER_LUMPS]) {
+        let mut lumps = source.lumps;
+        let mut data = source.data.clone();
+        let mut moved = Vec::new();
+        let ranges = source
+            .lumps
+            .map(|x| x.offset as usize..x.offset as usize + x.len as usize);
+        for lump in self.lump_order {
+            let old_range = ranges[lump].clone();
+            let new_data = &lump_data[lump];
+            let unchanged = if lump == LUMP_TEXTURES {
+                self.textures_match(&source.data[old_range.clone()])
+            } else {
+                **new_data == source.data[old_range.clone()]
+            };
+            if unchanged {
+                continue;
+            }
+
+            // Lumps can share or overlap their data, in which case the old bytes
+            // are left alone for the other lumps and the new data is appended
+            let shared = ranges.iter().enumerate().any(|(i, x)| {
+                i != lump && !x.is_empty() && x.start < old_range.end && old_range.start < x.end
+            });
+            if shared {
+                moved.push(lump);
+                continue;
+            }
+            data[old_range.clone()].fill(0);
+            if new_data.len() <= old_range.len() {
+                data[old_range.start..old_range.start + new_data.len()].copy_from_slice(new_data);
+                lumps[lump].len = new_data.len() as i32;
+            } else {
+                moved.push(lump);
+            }
+        }
+        for lump in moved {
+            lumps[lump] = append_lump(&mut data, &lump_data[lump]);
+        }
+        (data, lumps)
+    }
+
+    // Textures can share data or be stored out of order, which isn't kept when
+    // the lump is rebuilt, so they're compared one by one instead
+    fn textures_match(&self, source_lump: &[u8]) -> bool {
+        let mut offsets = Vec::new();
+        if let Some(count) = source_lump.get(..4) {
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            offsets = source_lump[4..]
+                .chunks_exact(4)
+                .take(count)
+                .map(|x| i32::from_le_bytes(x.try_into().unwrap()))
+                .collect();
+        }
+        let source_textures = BspTextureReader::new(&offsets, source_lump, false);
+        source_textures.len() == self.textures.len()
+            && self
+                .textures
+                .iter()
+                .enumerate()
+                .all(|(i, texture)| texture.as_deref() == source_textures.get_raw_data(i))
+    }
+
+    // In the standard lump order
+    fn lump_data(&self) -> [Cow<'_, [u8]>; HEADER_LUMPS] {
+        [
+            Cow::Borrowed(&self.entities),
+            Cow::Borrowed(as_bytes(&self.planes)),
+            Cow::Owned(self.texture_lump_bytes()),
+            Cow::Borrowed(as_bytes(&self.vertices)),
+            Cow::Borrowed(&self.visibility),
+            Cow::Borrowed(as_bytes(&self.nodes)),
+            Cow::Borrowed(as_bytes(&self.texture_infos)),
+            Cow::Borrowed(as_bytes(&self.faces)),
+            Cow::Borrowed(&self.lighting),
+            Cow::Borrowed(as_bytes(&self.clip_nodes)),
+            Cow::Borrowed(as_bytes(&self.leaves)),
+            Cow::Borrowed(as_bytes(&self.mark_surfaces)),
+            Cow::Borrowed(as_bytes(&self.edges)),
+            Cow::Borrowed(as_bytes(&self.surface_edges)),
+            Cow::Borrowed(as_bytes(&self.models)),
+        ]
+    }
+
+    fn texture_lump_bytes(&self) -> Vec<u8> {
+        // Maps without textures have an empty lump rather than a zero count
+        if self.textures.is_empty() {
+            return Vec::new();
+        }
+
+        let header_size = std::mem::size_of::<BspTextureHeader>()
+            + std::mem::size_of::<i32>() * self.textures.len();
+        let mut offsets = Vec::with_capacity(self.textures.len());
+        let mut texture_data = Vec::new();
+        for texture in &self.textures {
+            if let Some(texture) = texture {
+                offsets.push((header_size + texture_data.len()) as i32);
+                texture_data.extend_from_slice(texture);
+            } else {
+                offsets.push(-1);
+            }
+        }
+
+        let mut data = Vec::with_capacity(header_size + texture_data.len());
+        data.write_u32::<LittleEndian>(self.textures.len() as u32)
+            .unwrap();
+        for offset in offsets {
+            data.write_i32::<LittleEndian>(offset).unwrap();
+        }
+        data.extend_from_slice(&texture_data);
+        data
+    }
+}
+
+impl Default for BspFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Lumps start on 4 byte boundaries and are padded to them
+fn append_lump(data: &mut Vec<u8>, lump_data: &[u8]) -> BspLumpHeader {
+    data.resize(data.len().next_multiple_of(4), 0);
+    let header = BspLumpHeader {
+        offset: data.len() as i32,
+        len: lump_data.len() as i32,
+    };
+    data.extend_from_slice(lump_data);
+    data.resize(data.len().next_multiple_of(4), 0);
+    header
+}
+
+fn as_bytes<T: Copy>(values: &[T]) -> &[u8] {
+    // All lump types are repr(C) without padding
+    unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITIES: &[u8] = b"{\n\"classname\" \"worldspawn\"\n}\n\0";
+
+    fn miptex(name: &str) -> Vec<u8> {
+        // Name, size and mip offsets of 0 for a texture stored in a wad
+        let mut data = vec![0u8; 40];
+        data[..name.len()].copy_from_slice(name.as_bytes());
+        data[16..20].copy_from_slice(&16u32.to_le_bytes());
+        data[20..24].copy_from_slice(&16u32.to_le_bytes());
+        data
+    }
+
+    // A map laid out the way compilers don't: empty lumps at odd offsets, a
+    // texture shared by two entries and textures stored out of order
+    fn build_map() -> Vec<u8> {
+        let mut textures = Vec::new();
+        textures.extend_from_slice(&3u32.to_le_bytes());
+        for offset in [56i32, 16, 16] {
+            textures.extend_from_slice(&offset.to_le_bytes());
+        }
+        textures.extend_from_slice(&miptex("first"));
+        textures.extend_from_slice(&miptex("second"));
+
+        let mut planes = Vec::new();
+        for value in [0.0f32, 0.0, 1.0, 64.0] {
+            planes.extend_from_slice(&value.to_le_bytes());
+        }
+        planes.extend_from_slice(&2i32.to_le_bytes());
+
+        let header_size = std::mem::size_of::<super::super::BspHeader>();
+        let mut lumps = [BspLumpHeader { offset: 7, len: 0 }; HEADER_LUMPS];
+        let mut data = vec![0u8; header_size];
+        for (lump, lump_data) in [
+            (LUMP_TEXTURES, textures.as_slice()),
+            (LUMP_ENTITIES, ENTITIES),
+            (LUMP_PLANES, planes.as_slice()),
+        ] {
+            lumps[lump] = append_lump(&mut data, lump_data);
+        }
+
+        let mut header = Vec::new();
+        header.write_i32::<LittleEndian>(30).unwrap();
+        for lump in lumps {
+            header.write_i32::<LittleEndian>(lump.offset).unwrap();
+            header.write_i32::<LittleEndian>(lump.len).unwrap();
+        }
+        data[..header_size].copy_from_slice(&header);
+        data
+    }
+
+    #[test]
+    fn unmodified_round_trip() {
+        let data = build_map();
+        let reader = BspReader::from_slice(&data).unwrap();
+        let file = BspFile::from_reader(&reader);
+        assert_eq!(file.to_bytes(), data);
+    }
+
+    #[test]
+    fn modified_lump_is_moved() {
+        let data = build_map();
+        let reader = BspReader::from_slice(&data).unwrap();
+        let mut file = BspFile::from_reader(&reader);
+        let entities = "{\n\"classname\" \"worldspawn\"\n\"wad\" \"halflife.wad\"\n}\n";
+        file.set_entities_str(entities);
+
+        let bytes = file.to_bytes();
+        let reader = BspReader::from_slice(&bytes).unwrap();
+        assert_eq!(reader.read_entities_str().unwrap(), entities);
+        assert_eq!(reader.read_planes().len(), 1);
+        assert_eq!(reader.header().lumps[LUMP_TEXTURES].offset, 124);
+        assert_eq!(reader.header().lumps[LUMP_VERTICES].offset, 7);
+        let textures = reader.read_textures();
+        let names: Vec<_> = (0..textures.len())
+            .map(|i| textures.get(i).unwrap().get_image_name())
+            .collect();
+        assert_eq!(names, ["second", "first", "first"]);
+    }
+
+    #[test]
+    fn shared_lump_is_kept() {
+        // Point the vertex lump at the first 12 bytes of the plane lump
+        let mut data = build_map();
+        let reader = BspReader::from_slice(&data).unwrap();
+        let planes = reader.header().lumps[LUMP_PLANES];
+        let header = 4 + LUMP_VERTICES * 8;
+        data[header..header + 4].copy_from_slice(&planes.offset.to_le_bytes());
+        data[header + 4..header + 8].copy_from_slice(&12i32.to_le_bytes());
+        let positions = |reader: &BspReader| -> Vec<_> {
+            reader
+                .read_vertices()
+                .iter()
+                .map(|x| [x.x, x.y, x.z])
+                .collect()
+        };
+        let reader = BspReader::from_slice(&data).unwrap();
+        assert_eq!(positions(&reader), [[0.0, 0.0, 1.0]]);
+
+        let mut file = BspFile::from_reader(&reader);
+        file.planes[0].normal = [1.0, 0.0, 0.0];
+        let bytes = file.to_bytes();
+        let reader = BspReader::from_slice(&bytes).unwrap();
+        assert_eq!(reader.read_planes()[0].normal, [1.0, 0.0, 0.0]);
+        assert_eq!(positions(&reader), [[0.0, 0.0, 1.0]]);
+        assert_eq!(reader.header().lumps[LUMP_VERTICES].offset, planes.offset);
+        assert_ne!(reader.header().lumps[LUMP_PLANES].offset, planes.offset);
+    }
+
+    #[test]
+    fn empty_texture_lump() {
+        let mut file = BspFile::new();
+        file.set_entities_str("");
+        let bytes = file.to_bytes();
+        let reader = BspReader::from_slice(&bytes).unwrap();
+        assert_eq!(reader.header().lumps[LUMP_TEXTURES].len, 0);
+        assert!(reader.read_textures().is_empty());
+    }
+}
@@ -0,0 +1,577 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+
+use crate::util::resolve_map_entity_string;
+
+use super::{
+    BspEntity, BspLightmapAtlas, BspModelInstances, BspReader, BspResolvedTextures, BspTextureKind,
+};
+
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_VERSION: u32 = 2;
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const FILTER_LINEAR: u32 = 9729;
+const FILTER_LINEAR_MIPMAP_LINEAR: u32 = 9987;
+const WRAP_REPEAT: u32 = 10497;
+const WRAP_CLAMP_TO_EDGE: u32 = 33071;
+
+#[derive(Clone, Debug)]
+pub struct BspGltfOptions {
+    /// Multiplies every position, e.g. 0.0254 to convert units to meters.
+    pub unit_scale: f32,
+    /// Adds a second UV set and the lightmap atlas as a texture.
+    pub lightmaps: bool,
+    /// Directories to search for wads listed by worldspawn.
+    pub search_dirs: Vec<PathBuf>,
+    /// Leaves out `trigger_*` entities and faces with tool textures (`clip`,
+    /// `origin` and `aaatrigger`), which never render in game.
+    pub skip_tool_geometry: bool,
+}
+
+impl Default for BspGltfOptions {
+    fn default() -> Self {
+        Self {
+            unit_scale: 1.0,
+            lightmaps: true,
+            search_dirs: Vec::new(),
+            skip_tool_geometry: true,
+        }
+    }
+}
+
+/// A glTF 2.0 document and its binary buffer.
+pub struct BspGltf {
+    pub json: Value,
+    pub bin: Vec<u8>,
+}
+
+impl BspGltf {
+    /// Writes a .gltf file and a .bin file next to it with the same stem.
+    pub fn write_gltf<P: AsRef<Path>>(&self, gltf_path: P) -> std::io::Result<()> {
+        let gltf_path = gltf_path.as_ref();
+        let bin_path = gltf_path.with_extension("bin");
+        let bin_name = bin_path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or("scene.bin")
+            .to_owned();
+
+        let mut json = self.json.clone();
+        if let Some(buffer) = json.get_mut("buffers").and_then(|x| x.get_mut(0)) {
+            buffer["uri"] = Value::String(bin_name);
+        }
+        std::fs::write(gltf_path, serde_json::to_string_pretty(&json)?)?;
+        std::fs::write(bin_path, &self.bin)
+    }
+
+    pub fn write_glb<P: AsRef<Path>>(&self, glb_path: P) -> std::io::Result<()> {
+        std::fs::write(glb_path, self.to_glb())
+    }
+
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut json_data = serde_json::to_vec(&self.json).unwrap();
+        json_data.resize(json_data.len().next_multiple_of(4), b' ');
+        let mut bin_data = self.bin.clone();
+        bin_data.resize(bin_data.len().next_multiple_of(4), 0);
+
+        let total_len = 12 + 8 + json_data.len() + 8 + bin_data.len();
+        let mut data = Vec::with_capacity(total_len);
+        for value in [GLB_MAGIC, GLB_VERSION, total_len as u32] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&(json_data.len() as u32).to_le_bytes());
+        data.extend_from_slice(&GLB_CHUNK_JSON.to_le_bytes());
+        data.extend_from_slice(&json_data);
+        data.extend_from_slice(&(bin_data.len() as u32).to_le_bytes());
+        data.extend_from_slice(&GLB_CHUNK_BIN.to_le_bytes());
+        data.extend_from_slice(&bin_data);
+        data
+    }
+}
+
+/// GoldSrc is Z up, glTF is Y up with -Z forward.
+pub fn goldsrc_to_gltf(position: [f32; 3], scale: f32) -> [f32; 3] {
+    [
+        position[0] * scale,
+        position[2] * scale,
+        -position[1] * scale,
+    ]
+}
+
+#[derive(Default)]
+struct GltfBuilder {
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    images: Vec<Value>,
+    textures: Vec<Value>,
+    materials: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+}
+
+impl GltfBuilder {
+    fn push_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(data);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn push_floats<const N: usize>(&mut self, values: &[[f32; N]], with_bounds: bool) -> usize {
+        let data: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let view = self.push_view(&data, Some(ARRAY_BUFFER));
+        let ty = match N {
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "VEC4",
+        };
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": COMPONENT_FLOAT,
+            "count": values.len(),
+            "type": ty,
+        });
+        if with_bounds {
+            let mut mins = [f32::MAX; N];
+            let mut maxs = [f32::MIN; N];
+            for value in values {
+                for i in 0..N {
+                    mins[i] = mins[i].min(value[i]);
+                    maxs[i] = maxs[i].max(value[i]);
+                }
+            }
+            accessor["min"] = json!(mins.to_vec());
+            accessor["max"] = json!(maxs.to_vec());
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let data: Vec<u8> = indices.iter().flat_map(|x| x.to_le_bytes()).collect();
+        let view = self.push_view(&data, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": COMPONENT_UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn push_image<P: image::PixelWithColorType<Subpixel = u8>>(
+        &mut self,
+        name: &str,
+        image: &image::ImageBuffer<P, Vec<u8>>,
+        sampler: usize,
+    ) -> usize {
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let view = self.push_view(png.get_ref(), None);
+        self.images.push(json!({
+            "name": name,
+            "bufferView": view,
+            "mimeType": "image/png",
+        }));
+        self.textures.push(json!({
+            "sampler": sampler,
+            "source": self.images.len() - 1,
+        }));
+        self.textures.len() - 1
+    }
+}
+
+impl BspReader<'_> {
+    /// Exports the world and brush models as a glTF scene. Each brush entity gets
+    /// a node placed at its origin and rotated by its angles, and models no entity
+    /// uses are placed at the map origin. Models without any faces left to draw
+    /// are left out, as glTF doesn't allow empty meshes.
+    pub fn export_gltf(&self, options: &BspGltfOptions) -> BspGltf {
+        let scale = options.unit_scale;
+        let textures = self.resolve_textures(&options.search_dirs);
+        let atlas = if options.lightmaps {
+            Some(self.build_lightmap_atlas())
+        } else {
+            None
+        };
+
+        let mut builder = GltfBuilder::default();
+        let texture_sampler = 0;
+        let lightmap_sampler = 1;
+        let lightmap_texture = atlas
+            .as_ref()
+            .map(|atlas| builder.push_image("lightmap", &atlas.image, lightmap_sampler));
+        let texture_materials = push_materials(&mut builder, &textures, texture_sampler);
+
+        let texture_infos = self.read_texture_infos();
+        let faces = self.read_faces();
+        let mut model_meshes = Vec::new();
+        for model in self.build_model_meshes() {
+            let mut primitives = Vec::new();
+            for mesh in &model.meshes {
+                let is_tool = textures
+                    .textures
+                    .get(mesh.texture_index as usize)
+                    .is_some_and(|x| BspTextureKind::from_name(&x.name).is_tool());
+                if mesh.indices.is_empty() || (options.skip_tool_geometry && is_tool) {
+                    continue;
+                }
+                let positions: Vec<[f32; 3]> = mesh
+                    .vertices
+                    .iter()
+                    .map(|x| goldsrc_to_gltf(x.position, scale))
+                    .collect();
+                let normals: Vec<[f32; 3]> = mesh
+                    .vertices
+                    .iter()
+                    .map(|x| goldsrc_to_gltf(x.normal, 1.0))
+                    .collect();
+                let uvs: Vec<[f32; 2]> = mesh.vertices.iter().map(|x| x.uv).collect();
+                // Faces are wound clockwise, glTF expects counter-clockwise
+                let indices: Vec<u32> = mesh
+                    .indices
+                    .chunks_exact(3)
+                    .flat_map(|x| [x[0], x[2], x[1]])
+                    .collect();
+
+                let mut attributes = json!({
+                    "POSITION": builder.push_floats(&positions, true),
+                    "NORMAL": builder.push_floats(&normals, false),
+                    "TEXCOORD_0": builder.push_floats(&uvs, false),
+                });
+                if let Some(atlas) = &atlas {
+                    let lightmap_uvs = get_lightmap_uvs(atlas, mesh, faces, texture_infos);
+                    attributes["TEXCOORD_1"] = json!(builder.push_floats(&lightmap_uvs, false));
+                }
+
+                let mut primitive = json!({
+                    "attributes": attributes,
+                    "indices": builder.push_indices(&indices),
+                });
+                if let Some(material) = texture_materials.get(mesh.texture_index as usize) {
+                    primitive["material"] = json!(material);
+                }
+                primitives.push(primitive);
+            }
+            if primitives.is_empty() {
+                continue;
+            }
+            builder.meshes.push(json!({
+                "name": format!("model_{}", model.model_index),
+                "primitives": primitives,
+            }));
+            model_meshes.push((model.model_index, builder.meshes.len() - 1));
+        }
+
+        // Lightmaps aren't part of core glTF, so point materials at the atlas
+        // through extras
+        if let Some(lightmap_texture) = lightmap_texture {
+            for material in &mut builder.materials {
+                material["extras"] = json!({
+                    "lightmapTexture": { "index": lightmap_texture, "texCoord": 1 },
+                });
+            }
+        }
+
+        let entity_string = resolve_map_entity_string(self);
+        let entities = BspEntity::parse_entities(&entity_string).unwrap_or_default();
+        let instances = BspModelInstances::new(self, &entities);
+        let mut root_children = Vec::new();
+        for instance in &instances.instances {
+            let Some((_, mesh)) = model_meshes
+                .iter()
+                .find(|(x, _)| *x == instance.model_index)
+            else {
+                continue;
+            };
+            if options.skip_tool_geometry
+                && instance
+                    .entity_index
+                    .and_then(|x| entities[x].classname())
+                    .is_some_and(|x| x.starts_with("trigger_"))
+            {
+                continue;
+            }
+            let mut node = match instance.entity_index {
+                _ if instance.model_index == 0 => json!({ "name": "world" }),
+                Some(entity_index) => {
+                    let entity = &entities[entity_index];
+                    let name = entity
+                        .get("targetname")
+                        .map(|x| x.to_owned())
+                        .unwrap_or_else(|| {
+                            format!(
+                                "{}_{}",
+                                entity.classname().unwrap_or("entity"),
+                                entity_index
+                            )
+                        });
+                    json!({
+                        "name": name,
+                        "extras": { "entity": entity_index, "model": instance.model_index },
+                    })
+                }
+                None => json!({ "name": format!("model_{}", instance.model_index) }),
+            };
+            node["mesh"] = json!(mesh);
+            let transform = instance.transform;
+            if transform.origin != [0.0; 3] {
+                node["translation"] = json!(goldsrc_to_gltf(transform.origin, scale).to_vec());
+            }
+            if transform.is_rotated() {
+                let [x, y, z, w] = transform.rotation_quaternion();
+                let [x, y, z] = goldsrc_to_gltf([x, y, z], 1.0);
+                node["rotation"] = json!([x, y, z, w]);
+            }
+            builder.nodes.push(node);
+            root_children.push(builder.nodes.len() - 1);
+        }
+
+        let mut scene = json!({});
+        if !root_children.is_empty() {
+            scene["nodes"] = json!(root_children);
+        }
+        let mut json = json!({
+            "asset": { "version": "2.0", "generator": "gsparser" },
+            "scene": 0,
+            "scenes": [scene],
+        });
+        // glTF doesn't allow empty top-level arrays, so leave out the ones with
+        // nothing in them
+        let arrays = [
+            ("nodes", builder.nodes),
+            ("meshes", builder.meshes),
+            ("materials", builder.materials),
+            ("textures", builder.textures),
+            ("images", builder.images),
+            ("accessors", builder.accessors),
+            ("bufferViews", builder.buffer_views),
+        ];
+        for (key, values) in arrays {
+            if !values.is_empty() {
+                json[key] = Value::Array(values);
+            }
+        }
+        if json.get("textures").is_some() {
+            json["samplers"] = json!([
+                {
+                    "magFilter": FILTER_LINEAR,
+                    "minFilter": FILTER_LINEAR_MIPMAP_LINEAR,
+                    "wrapS": WRAP_REPEAT,
+                    "wrapT": WRAP_REPEAT,
+                },
+                {
+                    "magFilter": FILTER_LINEAR,
+                    "minFilter": FILTER_LINEAR,
+                    "wrapS": WRAP_CLAMP_TO_EDGE,
+                    "wrapT": WRAP_CLAMP_TO_EDGE,
+                },
+            ]);
+        }
+        if !builder.bin.is_empty() {
+            json["buffers"] = json!([{ "byteLength": builder.bin.len() }]);
+        }
+
+        BspGltf {
+            json,
+            bin: builder.bin,
+        }
+    }
+}
+
+// Returns the material index for each texture index
+fn push_materials(
+    builder: &mut GltfBuilder,
+    textures: &BspResolvedTextures,
+    sampler: usize,
+) -> Vec<usize> {
+    let mut materials = Vec::with_capacity(textures.textures.len());
+    for texture in &textures.textures {
+        let mut material = json!({
+            "name": texture.name,
+            "pbrMetallicRoughness": {
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+        });
+        if let Some(data) = &texture.data {
+            let texture_index = builder.push_image(&texture.name, &data.image, sampler);
+            material["pbrMetallicRoughness"]["baseColorTexture"] =
+                json!({ "index": texture_index });
+        } else {
+            // Missing textures show up as magenta
+            material["pbrMetallicRoughness"]["baseColorFactor"] = json!([1.0, 0.0, 1.0, 1.0]);
+        }
+        if texture.name.starts_with('{') {
+            material["alphaMode"] = json!("MASK");
+            material["alphaCutoff"] = json!(0.5);
+        }
+        builder.materials.push(material);
+        materials.push(builder.materials.len() - 1);
+    }
+    materials
+}
+
+fn get_lightmap_uvs(
+    atlas: &BspLightmapAtlas,
+    mesh: &super::BspTextureMesh,
+    faces: &[super::BspFace],
+    texture_infos: &[super::BspTextureInfo],
+) -> Vec<[f32; 2]> {
+    let mut uvs = vec![[0.0, 0.0]; mesh.vertices.len()];
+    for mesh_face in &mesh.faces {
+        let Some(texture_info) = faces
+            .get(mesh_face.face_index)
+            .and_then(|x| texture_infos.get(x.texture_info as usize))
+        else {
+            continue;
+        };
+        let range = mesh_face.first_vertex..mesh_face.first_vertex + mesh_face.vertices;
+        for (uv, vertex) in uvs[range.clone()].iter_mut().zip(&mesh.vertices[range]) {
+            if let Some(lightmap_uv) =
+                atlas.get_uv(mesh_face.face_index, 0, texture_info, vertex.position)
+            {
+                *uv = lightmap_uv;
+            }
+        }
+    }
+    uvs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BspModel, test_maps};
+    use super::*;
+
+    // Checks the schema rules empty maps tend to break: no empty top-level
+    // arrays or meshes, and no accessors without elements
+    fn check_structure(gltf: &BspGltf) {
+        let json = gltf.json.as_object().unwrap();
+        for (key, value) in json {
+            if let Some(values) = value.as_array() {
+                assert!(!values.is_empty(), "{key} is empty");
+            }
+        }
+        for scene in json["scenes"].as_array().unwrap() {
+            if let Some(nodes) = scene.get("nodes") {
+                assert!(!nodes.as_array().unwrap().is_empty());
+            }
+        }
+        for mesh in json
+            .get("meshes")
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+        {
+            assert!(!mesh["primitives"].as_array().unwrap().is_empty());
+        }
+        for accessor in json
+            .get("accessors")
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+        {
+            assert!(accessor["count"].as_u64().unwrap() >= 1);
+        }
+    }
+
+    fn node_names(gltf: &BspGltf) -> Vec<&str> {
+        gltf.json
+            .get("nodes")
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+            .map(|x| x["name"].as_str().unwrap())
+            .collect()
+    }
+
+    fn texture(name: &str) -> Vec<u8> {
+        // Stored in a wad, so only the name and size
+        let mut data = vec![0u8; 40];
+        data[..name.len()].copy_from_slice(name.as_bytes());
+        data[16..20].copy_from_slice(&16u32.to_le_bytes());
+        data[20..24].copy_from_slice(&16u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn single_face() {
+        let file = test_maps::single_face_map(8.0);
+        let reader = test_maps::read(&file);
+        for lightmaps in [true, false] {
+            let options = BspGltfOptions {
+                lightmaps,
+                ..Default::default()
+            };
+            let gltf = reader.export_gltf(&options);
+            check_structure(&gltf);
+            assert_eq!(node_names(&gltf), ["world"]);
+            assert_eq!(gltf.json["meshes"].as_array().unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn model_without_faces() {
+        let mut file = test_maps::single_face_map(8.0);
+        let mut model = file.models[0].clone();
+        model.faces = 0;
+        file.models.push(model);
+        file.set_entities_str(
+            "{\n\"classname\" \"worldspawn\"\n}\n{\n\"classname\" \"func_wall\"\n\"model\" \"*1\"\n}\n",
+        );
+        let gltf = test_maps::read(&file).export_gltf(&BspGltfOptions::default());
+        check_structure(&gltf);
+        assert_eq!(node_names(&gltf), ["world"]);
+    }
+
+    #[test]
+    fn tool_geometry() {
+        let mut file = test_maps::single_face_map(8.0);
+        file.textures.push(Some(texture("clip")));
+        file.models.push(BspModel {
+            first_face: 0,
+            faces: 1,
+            ..file.models[0].clone()
+        });
+        file.set_entities_str(
+            "{\n\"classname\" \"worldspawn\"\n}\n{\n\"classname\" \"trigger_once\"\n\"model\" \"*1\"\n}\n",
+        );
+        let reader = test_maps::read(&file);
+
+        // The only face is clip, so there's nothing left to draw
+        let gltf = reader.export_gltf(&BspGltfOptions::default());
+        check_structure(&gltf);
+        assert!(gltf.json.get("meshes").is_none());
+        assert!(gltf.json.get("nodes").is_none());
+        assert!(gltf.json["scenes"][0].get("nodes").is_none());
+
+        let options = BspGltfOptions {
+            skip_tool_geometry: false,
+            ..Default::default()
+        };
+        let gltf = reader.export_gltf(&options);
+        check_structure(&gltf);
+        assert_eq!(node_names(&gltf), ["world", "trigger_once_1"]);
+    }
+}
@@ -0,0 +1,252 @@
+use super::trace::BspHullTree;
+use super::winding::{Winding, base_winding, cross, dot, split_winding, winding_area};
+use super::{BspContents, BspHull, BspReader, BspTracePlane, FromValue};
+
+// Faces smaller than this are slivers left over from splitting
+const MIN_FACE_AREA: f64 = 0.01;
+// Points closer than this are merged into one vertex
+const WELD_EPSILON: f64 = 0.01;
+// Space left around the model bounds, so the outermost planes still cut
+const BOUNDS_PADDING: f64 = 1.0;
+
+/// A convex region of a hull with non-empty contents, such as solid.
+#[derive(Clone, Debug)]
+pub struct BspHullBrush {
+    pub contents: BspContents,
+    pub vertices: Vec<[f32; 3]>,
+    /// Convex polygons as indices into `vertices`, counter-clockwise seen from
+    /// outside the brush. Faces on the model bounds are included.
+    pub faces: Vec<Vec<u32>>,
+    /// The plane of each face, pointing out of the brush.
+    pub planes: Vec<BspTracePlane>,
+}
+
+impl BspHullBrush {
+    /// Fans every face into triangles, counter-clockwise seen from outside.
+    pub fn triangles(&self) -> Vec<[u32; 3]> {
+        let mut triangles = Vec::new();
+        for face in &self.faces {
+            for i in 1..face.len().saturating_sub(1) {
+                triangles.push([face[0], face[i], face[i + 1]]);
+            }
+        }
+        triangles
+    }
+
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let mut mins = [f32::MAX; 3];
+        let mut maxs = [f32::MIN; 3];
+        for vertex in &self.vertices {
+            for i in 0..3 {
+                mins[i] = mins[i].min(vertex[i]);
+                maxs[i] = maxs[i].max(vertex[i]);
+            }
+        }
+        (mins, maxs)
+    }
+}
+
+#[derive(Clone)]
+struct Face {
+    normal: [f64; 3],
+    dist: f64,
+    winding: Winding,
+}
+
+impl BspReader<'_> {
+    /// Rebuilds the non-empty regions of a model's hull as convex brushes by
+    /// cutting a box around the model down the hull's tree. The regions are in
+    /// the model's local space. Clip hulls are already expanded by the hull size,
+    /// so hull 1 brushes are what the player's center collides with.
+    pub fn build_hull_brushes(&self, model_index: usize, hull: BspHull) -> Vec<BspHullBrush> {
+        let mut brushes = Vec::new();
+        let Some(model) = self.read_models().get(model_index) else {
+            return brushes;
+        };
+        let Some(tree) = BspHullTree::new(self, model_index, hull) else {
+            return brushes;
+        };
+
+        let mins = [0, 1, 2].map(|i| (model.mins[i] + hull.mins()[i]) as f64 - BOUNDS_PADDING);
+        let maxs = [0, 1, 2].map(|i| (model.maxs[i] + hull.maxs()[i]) as f64 + BOUNDS_PADDING);
+        let base_size = mins
+            .iter()
+            .chain(maxs.iter())
+            .fold(0.0f64, |size, x| size.max(x.abs()))
+            * 2.0;
+        let mut bounds = Vec::new();
+        for i in 0..3 {
+            let mut normal = [0.0; 3];
+            normal[i] = 1.0;
+            bounds.push((normal, maxs[i]));
+            bounds.push((normal.map(|x| -x), -mins[i]));
+        }
+        let Some(bounds) = polyhedron_from_planes(&bounds, base_size) else {
+            return brushes;
+        };
+
+        // Each node is split at most once in a well formed tree
+        let mut budget = tree.max_depth() * 2 + 1;
+        let mut stack = vec![(tree.head_node(), bounds)];
+        while let Some((node, faces)) = stack.pop() {
+            if node < 0 {
+                let contents = BspContents::from_value(node).unwrap_or(BspContents::Solid);
+                if contents != BspContents::Empty {
+                    brushes.push(build_brush(contents, &faces));
+                }
+                continue;
+            }
+            if budget == 0 {
+                break;
+            }
+            budget -= 1;
+
+            let Some(plane) = tree.plane(node) else {
+                continue;
+            };
+            let normal = plane.normal.map(|x| x as f64);
+            let dist = plane.dist as f64;
+            let (front, back) = split_polyhedron(&faces, normal, dist, base_size);
+            for (side, faces) in [(0, front), (1, back)] {
+                if let (Some(faces), Some(child)) = (faces, tree.child(node, side)) {
+                    stack.push((child, faces));
+                }
+            }
+        }
+        brushes
+    }
+}
+
+fn polyhedron_from_planes(planes: &[([f64; 3], f64)], base_size: f64) -> Option<Vec<Face>> {
+    let mut faces = Vec::new();
+    for (i, (normal, dist)) in planes.iter().enumerate() {
+        let mut winding = Some(base_winding(*normal, *dist, base_size));
+        for (j, (other_normal, other_dist)) in planes.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let Some(current) = winding else {
+                break;
+            };
+            winding = split_winding(&current, *other_normal, *other_dist).1;
+        }
+        if let Some(winding) = winding.filter(|x| winding_area(x) >= MIN_FACE_AREA) {
+            faces.push(Face {
+                normal: *normal,
+                dist: *dist,
+                winding,
+            });
+        }
+    }
+    Some(faces).filter(|x| x.len() >= 4)
+}
+
+// Splits a convex polyhedron into the parts in front of and behind a plane,
+// closing each part with a cap on the plane. Parts that end up without volume
+// are `None`.
+fn split_polyhedron(
+    faces: &[Face],
+    normal: [f64; 3],
+    dist: f64,
+    base_size: f64,
+) -> (Option<Vec<Face>>, Option<Vec<Face>>) {
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    for face in faces {
+        // A face on the plane bounds the side it faces away from
+        let is_coplanar = (dot(face.normal, normal).abs() - 1.0).abs() < 1e-6
+            && (face.dist - dist * dot(face.normal, normal)).abs() < 0.01;
+        if is_coplanar {
+            if dot(face.normal, normal) > 0.0 {
+                back.push(face.clone());
+            } else {
+                front.push(face.clone());
+            }
+            continue;
+        }
+
+        let (front_winding, back_winding) = split_winding(&face.winding, normal, dist);
+        for (winding, side) in [(front_winding, &mut front), (back_winding, &mut back)] {
+            if let Some(winding) = winding.filter(|x| winding_area(x) >= MIN_FACE_AREA) {
+                side.push(Face {
+                    normal: face.normal,
+                    dist: face.dist,
+                    winding,
+                });
+            }
+        }
+    }
+
+    let mut cap = Some(base_winding(normal, dist, base_size));
+    for face in faces {
+        let Some(current) = cap else {
+            break;
+        };
+        cap = split_winding(&current, face.normal, face.dist).1;
+    }
+    if let Some(cap) = cap.filter(|x| winding_area(x) >= MIN_FACE_AREA) {
+        front.push(Face {
+            normal: normal.map(|x| -x),
+            dist: -dist,
+            winding: cap.clone(),
+        });
+        back.push(Face {
+            normal,
+            dist,
+            winding: cap,
+        });
+    }
+
+    let front = Some(front).filter(|x| x.len() >= 4);
+    let back = Some(back).filter(|x| x.len() >= 4);
+    (front, back)
+}
+
+fn build_brush(contents: BspContents, faces: &[Face]) -> BspHullBrush {
+    let mut vertices: Vec<[f64; 3]> = Vec::new();
+    let mut brush_faces = Vec::new();
+    let mut planes = Vec::new();
+    for face in faces {
+        let mut indices = Vec::with_capacity(face.winding.len());
+        for point in &face.winding {
+            let index = match vertices
+                .iter()
+                .position(|x| (0..3).all(|i| (x[i] - point[i]).abs() < WELD_EPSILON))
+            {
+                Some(index) => index,
+                None => {
+                    vertices.push(*point);
+                    vertices.len() - 1
+                }
+            } as u32;
+            if indices.last() != Some(&index) && indices.first() != Some(&index) {
+                indices.push(index);
+            }
+        }
+        if indices.len() < 3 {
+            continue;
+        }
+
+        // Windings are clockwise seen from the front, flip them if needed
+        let w = &face.winding;
+        let winding_normal = cross(
+            [0, 1, 2].map(|i| w[1][i] - w[0][i]),
+            [0, 1, 2].map(|i| w[2][i] - w[0][i]),
+        );
+        if dot(winding_normal, face.normal) < 0.0 {
+            indices.reverse();
+        }
+        brush_faces.push(indices);
+        planes.push(BspTracePlane {
+            normal: face.normal.map(|x| x as f32),
+            dist: face.dist as f32,
+        });
+    }
+
+    BspHullBrush {
+        contents,
+        vertices: vertices.iter().map(|x| x.map(|x| x as f32)).collect(),
+        faces: brush_faces,
+        planes,
+    }
+}
@@ -0,0 +1,503 @@
+use super::{
+    BspContents, BspHeader, BspReader, FromValue, LUMP_CLIPNODES, LUMP_EDGES, LUMP_FACES,
+    LUMP_LEAVES, LUMP_LIGHTING, LUMP_MARKSURFACES, LUMP_MODELS, LUMP_NAMES, LUMP_NODES,
+    LUMP_PLANES, LUMP_SURFEDGES, LUMP_TEXINFO, LUMP_TEXTURES, LUMP_VERTICES, LUMP_VISIBILITY,
+    NO_LIGHT_STYLE,
+};
+
+// How far a plane normal's length may be from 1 before it's reported
+const NORMAL_EPSILON: f32 = 0.001;
+
+/// A broken reference or malformed value found by `verify_integrity`. Elements
+/// are identified by their lump and index within it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BspIntegrityIssue {
+    /// A field holds an index (or byte offset) outside the lump it refers to.
+    IndexOutOfBounds {
+        lump: usize,
+        index: usize,
+        field: &'static str,
+        target_lump: usize,
+        value: i64,
+        len: usize,
+    },
+    /// A first and count pair reaches outside the lump it refers to.
+    RangeOutOfBounds {
+        lump: usize,
+        index: usize,
+        field: &'static str,
+        target_lump: usize,
+        first: i64,
+        count: usize,
+        len: usize,
+    },
+    /// A leaf or clip node child holds a value that isn't a known contents type.
+    UnknownContents {
+        lump: usize,
+        index: usize,
+        contents: i32,
+    },
+    NonUnitNormal {
+        plane_index: usize,
+        length: f32,
+    },
+    /// A lit face is too large for its lightmap size to be computed, usually
+    /// because of corrupt vertices.
+    BadFaceExtents {
+        face_index: usize,
+    },
+    /// A lump starts inside the file header.
+    OverlapsHeader {
+        lump: usize,
+    },
+    OverlappingLumps {
+        lumps: [usize; 2],
+    },
+}
+
+impl std::fmt::Display for BspIntegrityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BspIntegrityIssue::IndexOutOfBounds {
+                lump,
+                index,
+                field,
+                target_lump,
+                value,
+                len,
+            } => write!(
+                f,
+                "{} {}: {} {} is outside the {} lump ({} long)",
+                LUMP_NAMES[*lump], index, field, value, LUMP_NAMES[*target_lump], len
+            ),
+            BspIntegrityIssue::RangeOutOfBounds {
+                lump,
+                index,
+                field,
+                target_lump,
+                first,
+                count,
+                len,
+            } => write!(
+                f,
+                "{} {}: {} {}..{} is outside the {} lump ({} long)",
+                LUMP_NAMES[*lump],
+                index,
+                field,
+                first,
+                first + *count as i64,
+                LUMP_NAMES[*target_lump],
+                len
+            ),
+            BspIntegrityIssue::UnknownContents {
+                lump,
+                index,
+                contents,
+            } => write!(
+                f,
+                "{} {}: unknown contents {}",
+                LUMP_NAMES[*lump], index, contents
+            ),
+            BspIntegrityIssue::NonUnitNormal {
+                plane_index,
+                length,
+            } => write!(
+                f,
+                "Planes {}: normal has length {} instead of 1",
+                plane_index, length
+            ),
+            BspIntegrityIssue::BadFaceExtents { face_index } => {
+                write!(
+                    f,
+                    "Faces {}: extents are too large for a lightmap",
+                    face_index
+                )
+            }
+            BspIntegrityIssue::OverlapsHeader { lump } => {
+                write!(f, "{} lump starts inside the header", LUMP_NAMES[*lump])
+            }
+            BspIntegrityIssue::OverlappingLumps { lumps } => write!(
+                f,
+                "{} and {} lumps overlap",
+                LUMP_NAMES[lumps[0]], LUMP_NAMES[lumps[1]]
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct IssueCollector {
+    issues: Vec<BspIntegrityIssue>,
+}
+
+impl IssueCollector {
+    fn check_index(
+        &mut self,
+        (lump, index): (usize, usize),
+        field: &'static str,
+        target_lump: usize,
+        value: i64,
+        len: usize,
+    ) {
+        if value < 0 || value as u64 >= len as u64 {
+            self.issues.push(BspIntegrityIssue::IndexOutOfBounds {
+                lump,
+                index,
+                field,
+                target_lump,
+                value,
+                len,
+            });
+        }
+    }
+
+    fn check_range(
+        &mut self,
+        (lump, index): (usize, usize),
+        field: &'static str,
+        target_lump: usize,
+        first: i64,
+        count: usize,
+        len: usize,
+    ) {
+        if first < 0 || first as u64 + count as u64 > len as u64 {
+            self.issues.push(BspIntegrityIssue::RangeOutOfBounds {
+                lump,
+                index,
+                field,
+                target_lump,
+                first,
+                count,
+                len,
+            });
+        }
+    }
+
+    fn check_contents(&mut self, (lump, index): (usize, usize), contents: i32) {
+        if BspContents::from_value(contents).is_none() {
+            self.issues.push(BspIntegrityIssue::UnknownContents {
+                lump,
+                index,
+                contents,
+            });
+        }
+    }
+}
+
+impl BspReader<'_> {
+    /// Checks every reference between lumps, plane normals and the lump layout.
+    /// The reader only validates what it needs to read lumps safely, so maps that
+    /// load fine here can still crash the engine if this finds anything.
+    pub fn verify_integrity(&self) -> Vec<BspIntegrityIssue> {
+        let mut collector = IssueCollector::default();
+        self.verify_lump_layout(&mut collector);
+
+        let planes = self.read_planes();
+        let nodes = self.read_nodes();
+        let clip_nodes = self.read_clip_nodes();
+        let leaves = self.read_leaves();
+        let mark_surfaces = self.read_mark_surfaces();
+        let faces = self.read_faces();
+        let texture_infos = self.read_texture_infos();
+        let surface_edges = self.read_surface_edges();
+        let edges = self.read_edges();
+        let vertices = self.read_vertices();
+        let lighting_len = self.read_lighting_data().len();
+        let luxel_size = self.variant().lightmap_luxel_size();
+        let visibility_len = self.read_visibility_data().len();
+        let num_textures = self.read_textures_header().num_textures as usize;
+
+        for (plane_index, plane) in planes.iter().enumerate() {
+            let length = plane.normal.iter().map(|x| x * x).sum::<f32>().sqrt();
+            // NaN lengths are reported too
+            if (length - 1.0).abs() > NORMAL_EPSILON || length.is_nan() {
+                collector.issues.push(BspIntegrityIssue::NonUnitNormal {
+                    plane_index,
+                    length,
+                });
+            }
+        }
+
+        for (index, node) in nodes.iter().enumerate() {
+            let element = (LUMP_NODES, index);
+            collector.check_index(
+                element,
+                "plane",
+                LUMP_PLANES,
+                node.plane as i64,
+                planes.len(),
+            );
+            for child in node.children {
+                if child >= 0 {
+                    collector.check_index(element, "child", LUMP_NODES, child as i64, nodes.len());
+                } else {
+                    let leaf = -(child as i64) - 1;
+                    collector.check_index(element, "child", LUMP_LEAVES, leaf, leaves.len());
+                }
+            }
+            collector.check_range(
+                element,
+                "faces",
+                LUMP_FACES,
+                node.first_face as i64,
+                node.faces as usize,
+                faces.len(),
+            );
+        }
+
+        for (index, clip_node) in clip_nodes.iter().enumerate() {
+            let element = (LUMP_CLIPNODES, index);
+            collector.check_index(
+                element,
+                "plane",
+                LUMP_PLANES,
+                clip_node.plane_index as i64,
+                planes.len(),
+            );
+            for child in clip_node.children {
+                if child >= 0 {
+                    collector.check_index(
+                        element,
+                        "child",
+                        LUMP_CLIPNODES,
+                        child as i64,
+                        clip_nodes.len(),
+                    );
+                } else {
+                    collector.check_contents(element, child as i32);
+                }
+            }
+        }
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            let element = (LUMP_LEAVES, index);
+            collector.check_contents(element, leaf.contents);
+            collector.check_range(
+                element,
+                "mark surfaces",
+                LUMP_MARKSURFACES,
+                leaf.first_mark_surface as i64,
+                leaf.mark_surfaces as usize,
+                mark_surfaces.len(),
+            );
+            // -1 means the leaf has no visibility data
+            if leaf.vis_offset >= 0 {
+                collector.check_index(
+                    element,
+                    "vis offset",
+                    LUMP_VISIBILITY,
+                    leaf.vis_offset as i64,
+                    visibility_len,
+                );
+            }
+        }
+
+        for (index, mark_surface) in mark_surfaces.iter().enumerate() {
+            collector.check_index(
+                (LUMP_MARKSURFACES, index),
+                "face",
+                LUMP_FACES,
+                mark_surface.0 as i64,
+                faces.len(),
+            );
+        }
+
+        for (index, face) in faces.iter().enumerate() {
+            let element = (LUMP_FACES, index);
+            collector.check_index(
+                element,
+                "plane",
+                LUMP_PLANES,
+                face.plane as i64,
+                planes.len(),
+            );
+            collector.check_range(
+                element,
+                "edges",
+                LUMP_SURFEDGES,
+                face.first_edge as i64,
+                face.edges as usize,
+                surface_edges.len(),
+            );
+            collector.check_index(
+                element,
+                "texture info",
+                LUMP_TEXINFO,
+                face.texture_info as i64,
+                texture_infos.len(),
+            );
+            // -1 means the face has no lightmap. Like the engine, styles stop at
+            // the first unused slot.
+            let styles = face
+                .styles
+                .iter()
+                .take_while(|x| **x != NO_LIGHT_STYLE)
+                .count();
+            if face.lightmap_offset < 0 || styles == 0 {
+                continue;
+            }
+            match self.get_face_extents(face) {
+                Some(extents) => {
+                    let luxels =
+                        extents.lightmap_width() as usize * extents.lightmap_height() as usize;
+                    collector.check_range(
+                        element,
+                        "lightmap",
+                        LUMP_LIGHTING,
+                        face.lightmap_offset as i64,
+                        luxels.saturating_mul(styles * luxel_size),
+                        lighting_len,
+                    );
+                }
+                // Broken references were reported above, anything else means
+                // the face's vertices are too far apart
+                None => {
+                    let has_vertices = texture_infos.get(face.texture_info as usize).is_some()
+                        && self.get_face_vertices(face).is_some_and(|x| !x.is_empty());
+                    if has_vertices {
+                        collector
+                            .issues
+                            .push(BspIntegrityIssue::BadFaceExtents { face_index: index });
+                    }
+                }
+            }
+        }
+
+        for (index, texture_info) in texture_infos.iter().enumerate() {
+            collector.check_index(
+                (LUMP_TEXINFO, index),
+                "texture",
+                LUMP_TEXTURES,
+                texture_info.texture_index as i64,
+                num_textures,
+            );
+        }
+
+        for (index, surface_edge) in surface_edges.iter().enumerate() {
+            // The sign only picks the edge's direction
+            collector.check_index(
+                (LUMP_SURFEDGES, index),
+                "edge",
+                LUMP_EDGES,
+                (surface_edge.0 as i64).abs(),
+                edges.len(),
+            );
+        }
+
+        for (index, edge) in edges.iter().enumerate() {
+            for vertex in edge.vertices {
+                collector.check_index(
+                    (LUMP_EDGES, index),
+                    "vertex",
+                    LUMP_VERTICES,
+                    vertex as i64,
+                    vertices.len(),
+                );
+            }
+        }
+
+        for (index, model) in self.read_models().iter().enumerate() {
+            let element = (LUMP_MODELS, index);
+            collector.check_index(
+                element,
+                "head node",
+                LUMP_NODES,
+                model.head_nodes[0] as i64,
+                nodes.len(),
+            );
+            for head_node in &model.head_nodes[1..] {
+                // Negative clip hull heads are contents, used by models without
+                // collision
+                if *head_node >= 0 {
+                    collector.check_index(
+                        element,
+                        "clip head node",
+                        LUMP_CLIPNODES,
+                        *head_node as i64,
+                        clip_nodes.len(),
+                    );
+                } else {
+                    collector.check_contents(element, *head_node);
+                }
+            }
+            collector.check_range(
+                element,
+                "faces",
+                LUMP_FACES,
+                model.first_face as i64,
+                model.faces.max(0) as usize,
+                faces.len(),
+            );
+            // Leaf 0 isn't counted in vis_leaves
+            collector.check_range(
+                element,
+                "vis leaves",
+                LUMP_LEAVES,
+                1,
+                model.vis_leaves.max(0) as usize,
+                leaves.len(),
+            );
+        }
+
+        collector.issues
+    }
+
+    fn verify_lump_layout(&self, collector: &mut IssueCollector) {
+        let header_size = size_of::<BspHeader>();
+        let lumps = self.header.lumps;
+        for (lump, a) in lumps.iter().enumerate() {
+            if a.len == 0 {
+                continue;
+            }
+            if (a.offset as usize) < header_size {
+                collector
+                    .issues
+                    .push(BspIntegrityIssue::OverlapsHeader { lump });
+            }
+            for (other, b) in lumps.iter().enumerate().skip(lump + 1) {
+                if b.len == 0 {
+                    continue;
+                }
+                // Offsets and lengths were checked to be positive and in the file
+                let a_end = a.offset as usize + a.len as usize;
+                let b_end = b.offset as usize + b.len as usize;
+                if (a.offset as usize) < b_end && (b.offset as usize) < a_end {
+                    collector.issues.push(BspIntegrityIssue::OverlappingLumps {
+                        lumps: [lump, other],
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+    use super::*;
+
+    #[test]
+    fn lightmap_out_of_bounds() {
+        let mut file = test_maps::single_face_map(8.0);
+        // A 3x3 lightmap needs 27 bytes
+        file.lighting.truncate(26);
+        let reader = test_maps::read(&file);
+        let issues = reader.verify_integrity();
+        assert!(issues.contains(&BspIntegrityIssue::RangeOutOfBounds {
+            lump: LUMP_FACES,
+            index: 0,
+            field: "lightmap",
+            target_lump: LUMP_LIGHTING,
+            first: 0,
+            count: 27,
+            len: 26,
+        }));
+    }
+
+    #[test]
+    fn hostile_vertices() {
+        let reader = test_maps::read(&test_maps::single_face_map(1e30));
+        let issues = reader.verify_integrity();
+        assert!(issues.contains(&BspIntegrityIssue::BadFaceExtents { face_index: 0 }));
+    }
+}
@@ -0,0 +1,179 @@
+use super::{BspLightStyles, BspNodeChild, BspReader};
+
+// How far below the point R_LightPoint looks for a surface
+const LIGHT_POINT_DISTANCE: f32 = 2048.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BspLightSample {
+    /// Light in lightmap units (0-255 at normal brightness), with every style of
+    /// the surface added up. Bright styles can go past 255.
+    pub color: [f32; 3],
+    /// The face the light was sampled from.
+    pub face_index: usize,
+    /// Where the trace hit the face.
+    pub position: [f32; 3],
+}
+
+impl BspReader<'_> {
+    /// Finds the world surface straight below a point and samples its lightmap
+    /// there, like the engine does to light studio models. Luxels are bilinearly
+    /// filtered, where the engine takes the nearest one. Returns `None` if nothing
+    /// lit is within 2048 units below the point.
+    pub fn sample_light_point(
+        &self,
+        point: [f32; 3],
+        light_styles: &BspLightStyles,
+        time: f32,
+    ) -> Option<BspLightSample> {
+        let world = self.read_models().first()?;
+        if world.head_nodes[0] < 0 {
+            return None;
+        }
+        let end = [point[0], point[1], point[2] - LIGHT_POINT_DISTANCE];
+        let style_values = light_styles.get_values(time);
+        let mut budget = self.read_nodes().len();
+        self.light_point_recursive(
+            BspNodeChild::Node(world.head_nodes[0] as usize),
+            point,
+            end,
+            &style_values,
+            &mut budget,
+        )
+        .flatten()
+    }
+
+    // Follows RecursiveLightPoint. Returns Some(None) when the trace hit a surface
+    // without light, which stops the search like the engine does.
+    fn light_point_recursive(
+        &self,
+        child: BspNodeChild,
+        start: [f32; 3],
+        end: [f32; 3],
+        style_values: &[f32],
+        budget: &mut usize,
+    ) -> Option<Option<BspLightSample>> {
+        let BspNodeChild::Node(node_index) = child else {
+            return None;
+        };
+        // Guards against cycles in a corrupt tree
+        if *budget == 0 {
+            return None;
+        }
+        *budget -= 1;
+        let node = self.read_nodes().get(node_index)?;
+        let plane = self.read_planes().get(node.plane as usize)?;
+
+        let front = plane.distance_to(start);
+        let back = plane.distance_to(end);
+        let side = (front < 0.0) as usize;
+        if (back < 0.0) as usize == side {
+            return self.light_point_recursive(node.child(side), start, end, style_values, budget);
+        }
+
+        let fraction = front / (front - back);
+        let mid = [0, 1, 2].map(|i| start[i] + (end[i] - start[i]) * fraction);
+        if let Some(result) =
+            self.light_point_recursive(node.child(side), start, mid, style_values, budget)
+        {
+            return Some(result);
+        }
+
+        let first_face = node.first_face as usize;
+        for face_index in first_face..first_face + node.faces as usize {
+            let face = self.read_faces().get(face_index)?;
+            let texture_info = self.read_texture_infos().get(face.texture_info as usize)?;
+            if texture_info.is_special() {
+                continue;
+            }
+            // Extents are checked, so corrupt faces too large for a lightmap
+            // are skipped here rather than overflowing below
+            let Some(extents) = self.get_face_extents(face) else {
+                continue;
+            };
+            let [s, t] = texture_info.get_texel_coords(mid);
+            let ds = s - extents.texture_mins[0] as f32;
+            let dt = t - extents.texture_mins[1] as f32;
+            if ds < 0.0
+                || dt < 0.0
+                || ds > extents.extents[0] as f32
+                || dt > extents.extents[1] as f32
+            {
+                continue;
+            }
+
+            let Some(lightmap) = self.get_face_lightmap(face_index) else {
+                return Some(None);
+            };
+            let [u, v] = extents.get_luxel_coords(texture_info, mid);
+            let mut color = [0.0; 3];
+            for style in &lightmap.styles {
+                let value = style_values
+                    .get(style.style as usize)
+                    .copied()
+                    .unwrap_or(1.0);
+                let luxel = sample_bilinear(&style.data, lightmap.width, lightmap.height, u, v);
+                for i in 0..3 {
+                    color[i] += luxel[i] * value;
+                }
+            }
+            return Some(Some(BspLightSample {
+                color,
+                face_index,
+                position: mid,
+            }));
+        }
+
+        self.light_point_recursive(node.child(side ^ 1), mid, end, style_values, budget)
+    }
+}
+
+// Samples RGB luxels at a position in luxels, where the first luxel's center is
+// at (0.5, 0.5)
+fn sample_bilinear(data: &[u8], width: u32, height: u32, u: f32, v: f32) -> [f32; 3] {
+    let x = (u - 0.5).clamp(0.0, (width - 1) as f32);
+    let y = (v - 0.5).clamp(0.0, (height - 1) as f32);
+    let x0 = x.floor() as usize;
+    let y0 = y.floor() as usize;
+    let x1 = (x0 + 1).min(width as usize - 1);
+    let y1 = (y0 + 1).min(height as usize - 1);
+    let fx = x - x0 as f32;
+    let fy = y - y0 as f32;
+
+    let luxel = |x: usize, y: usize| {
+        let offset = (y * width as usize + x) * 3;
+        [0, 1, 2].map(|i| data[offset + i] as f32)
+    };
+    let [a, b, c, d] = [luxel(x0, y0), luxel(x1, y0), luxel(x0, y1), luxel(x1, y1)];
+    [0, 1, 2].map(|i| {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        top + (bottom - top) * fy
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+    use super::*;
+
+    #[test]
+    fn sample_face() {
+        let reader = test_maps::read(&test_maps::single_face_map(8.0));
+        let light_styles = BspLightStyles::default();
+        let sample = reader
+            .sample_light_point([0.0, 0.0, 64.0], &light_styles, 0.0)
+            .unwrap();
+        assert_eq!(sample.face_index, 0);
+        assert_eq!(sample.position, [0.0, 0.0, 0.0]);
+        // Every luxel is 128, scaled by style 0
+        let expected = 128.0 * light_styles.get_value(0, 0.0);
+        assert!(sample.color.iter().all(|x| (x - expected).abs() < 0.01));
+    }
+
+    #[test]
+    fn hostile_vertices() {
+        let reader = test_maps::read(&test_maps::single_face_map(1e30));
+        let sample = reader.sample_light_point([0.0, 0.0, 64.0], &BspLightStyles::default(), 0.0);
+        assert!(sample.is_none());
+    }
+}
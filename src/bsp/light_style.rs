@@ -0,0 +1,153 @@
+use crate::util::resolve_map_entity_string;
+
+use super::{BspEntity, BspEntityParseError, BspFaceLightmap, BspReader};
+
+/// The number of light styles the engine tracks. Styles 0-31 are defined by the
+/// game, 32-62 are given to switchable lights and 63 is reserved for testing.
+pub const MAX_LIGHT_STYLE_PATTERNS: usize = 64;
+
+/// The first style given to switchable lights.
+pub const FIRST_SWITCHABLE_LIGHT_STYLE: usize = 32;
+
+// Pattern characters advance at 10 per second
+const LIGHT_STYLE_FRAME_RATE: f32 = 10.0;
+
+// From CWorld::Precache in the Half-Life SDK
+const DEFAULT_PATTERNS: [(usize, &str); 14] = [
+    // Normal
+    (0, "m"),
+    // Flicker A
+    (1, "mmnmmommommnonmmonqnmmo"),
+    // Slow strong pulse
+    (2, "abcdefghijklmnopqrstuvwxyzyxwvutsrqponmlkjihgfedcba"),
+    // Candle A
+    (3, "mmmmmaaaaammmmmaaaaaabcdefgabcdefg"),
+    // Fast strobe
+    (4, "mamamamamama"),
+    // Gentle pulse
+    (5, "jklmnopqrstuvwxyzyxwvutsrqponmlkj"),
+    // Flicker B
+    (6, "nmonqnmomnmomomno"),
+    // Candle B
+    (7, "mmmaaaabcdefgmmmmaaaammmaamm"),
+    // Candle C
+    (8, "mmmaaammmaaammmabcdefaaaammmmabcdefmmmaaaa"),
+    // Slow strobe
+    (9, "aaaaaaaazzzzzzzz"),
+    // Fluorescent flicker
+    (10, "mmamammmmammamamaaamammma"),
+    // Slow pulse, not fading to black
+    (11, "abcdefghijklmnopqrrqponmlkjihgfedcba"),
+    // Underwater light mutation
+    (12, "mmnnmmnnnmmnn"),
+    // Testing
+    (63, "a"),
+];
+
+// CLight spawnflag that starts a switchable light off
+const SF_LIGHT_START_OFF: i32 = 1;
+
+/// Maps light style numbers to their pattern strings, where 'a' is dark, 'm' is
+/// normal brightness and 'z' is double brightness.
+#[derive(Clone, Debug)]
+pub struct BspLightStyles {
+    patterns: Vec<Option<String>>,
+}
+
+impl Default for BspLightStyles {
+    /// Returns the game's default styles.
+    fn default() -> Self {
+        let mut styles = Self {
+            patterns: vec![None; MAX_LIGHT_STYLE_PATTERNS],
+        };
+        for (style, pattern) in DEFAULT_PATTERNS {
+            styles.set_pattern(style, pattern);
+        }
+        styles
+    }
+}
+
+impl BspLightStyles {
+    /// Returns the default styles plus the switchable styles set up by the map's
+    /// light entities, the same way CLight::Spawn does. Lights that start off get
+    /// the pattern "a".
+    pub fn new(entities: &[BspEntity]) -> Self {
+        let mut styles = Self::default();
+        for entity in entities {
+            if !entity.classname().is_some_and(|x| x.starts_with("light")) {
+                continue;
+            }
+            let Some(style) = entity
+                .get("style")
+                .and_then(|x| x.trim().parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if !(FIRST_SWITCHABLE_LIGHT_STYLE..MAX_LIGHT_STYLE_PATTERNS).contains(&style) {
+                continue;
+            }
+            let spawn_flags = entity
+                .get("spawnflags")
+                .and_then(|x| x.trim().parse::<i32>().ok())
+                .unwrap_or(0);
+            let pattern = if spawn_flags & SF_LIGHT_START_OFF != 0 {
+                "a"
+            } else {
+                entity.get("pattern").unwrap_or("m")
+            };
+            styles.set_pattern(style, pattern);
+        }
+        styles
+    }
+
+    pub fn from_reader(reader: &BspReader) -> Result<Self, BspEntityParseError> {
+        let entity_string = resolve_map_entity_string(reader);
+        let entities = BspEntity::parse_entities(&entity_string)?;
+        Ok(Self::new(&entities))
+    }
+
+    pub fn get_pattern(&self, style: usize) -> Option<&str> {
+        self.patterns.get(style)?.as_deref()
+    }
+
+    /// Sets the pattern of a style, e.g. to switch a light on or off. Styles past
+    /// `MAX_LIGHT_STYLE_PATTERNS` are ignored.
+    pub fn set_pattern(&mut self, style: usize, pattern: &str) {
+        if let Some(slot) = self.patterns.get_mut(style) {
+            *slot = Some(pattern.to_owned());
+        }
+    }
+
+    /// Returns the brightness of a style at a time in seconds, where 1.0 is the
+    /// lightmap's own brightness. Matches R_AnimateLight, so 'm' is slightly
+    /// brighter than 1.0 and styles without a pattern are exactly 1.0.
+    pub fn get_value(&self, style: usize, time: f32) -> f32 {
+        let Some(pattern) = self.get_pattern(style).filter(|x| !x.is_empty()) else {
+            return 1.0;
+        };
+        let pattern = pattern.as_bytes();
+        let frame = (time * LIGHT_STYLE_FRAME_RATE).max(0.0) as usize % pattern.len();
+        let value = pattern[frame].saturating_sub(b'a') as u32 * 22;
+        value as f32 / 256.0
+    }
+
+    /// Returns the brightness of every style at a time in seconds.
+    pub fn get_values(&self, time: f32) -> [f32; MAX_LIGHT_STYLE_PATTERNS] {
+        std::array::from_fn(|style| self.get_value(style, time))
+    }
+}
+
+impl BspFaceLightmap<'_> {
+    /// Adds up the lightmaps of every style scaled by its brightness at the given
+    /// time, returning RGB luxels (`width * height * 3` bytes).
+    pub fn composite(&self, light_styles: &BspLightStyles, time: f32) -> Vec<u8> {
+        let mut light = vec![0.0f32; self.width as usize * self.height as usize * 3];
+        for style in &self.styles {
+            let value = light_styles.get_value(style.style as usize, time);
+            for (light, luxel) in light.iter_mut().zip(style.data.iter()) {
+                *light += *luxel as f32 * value;
+            }
+        }
+        light.iter().map(|x| x.round().min(255.0) as u8).collect()
+    }
+}
@@ -0,0 +1,340 @@
+use std::borrow::Cow;
+
+use super::{BspFace, BspReader, BspTextureInfo};
+
+pub const LUXEL_SIZE: i32 = 16;
+pub const MAX_LIGHT_STYLES: usize = 4;
+pub const NO_LIGHT_STYLE: u8 = 255;
+
+// Far past the engine's limit of 256, but small enough that lightmap sizes can't
+// overflow
+const MAX_FACE_EXTENT: i64 = 1 << 24;
+
+/// The texture space bounds of a face, snapped to the luxel grid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BspFaceExtents {
+    pub texture_mins: [i32; 2],
+    pub extents: [i32; 2],
+}
+
+impl BspFaceExtents {
+    pub fn lightmap_width(&self) -> u32 {
+        (self.extents[0] / LUXEL_SIZE) as u32 + 1
+    }
+
+    pub fn lightmap_height(&self) -> u32 {
+        (self.extents[1] / LUXEL_SIZE) as u32 + 1
+    }
+
+    /// Returns the position of a point within the lightmap, in luxels. The
+    /// center of the first luxel is at (0.5, 0.5).
+    pub fn get_luxel_coords(&self, texture_info: &BspTextureInfo, position: [f32; 3]) -> [f32; 2] {
+        let [s, t] = texture_info.get_texel_coords(position);
+        let half_luxel = (LUXEL_SIZE / 2) as f32;
+        [
+            (s - self.texture_mins[0] as f32 + half_luxel) / LUXEL_SIZE as f32,
+            (t - self.texture_mins[1] as f32 + half_luxel) / LUXEL_SIZE as f32,
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BspLightmapStyle<'a> {
+    pub style: u8,
+    /// RGB luxels, `width * height * 3` bytes. Quake's mono lightmaps are
+    /// expanded to RGB.
+    pub data: Cow<'a, [u8]>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BspFaceLightmap<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub extents: BspFaceExtents,
+    pub styles: Vec<BspLightmapStyle<'a>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BspAtlasRect {
+    pub style: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Where a face's lightmaps were placed in a `BspLightmapAtlas`.
+#[derive(Clone, Debug)]
+pub struct BspLightmapAtlasEntry {
+    pub width: u32,
+    pub height: u32,
+    pub extents: BspFaceExtents,
+    /// One rect per light style, in the same order as `BspFace::styles`.
+    pub rects: Vec<BspAtlasRect>,
+}
+
+pub struct BspLightmapAtlas {
+    pub image: image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    /// Indexed by face index, `None` for faces without a lightmap.
+    pub entries: Vec<Option<BspLightmapAtlasEntry>>,
+}
+
+impl BspLightmapAtlas {
+    // Each lightmap gets a border of duplicated edge luxels so bilinear
+    // filtering doesn't bleed between neighbors.
+    const PADDING: u32 = 1;
+
+    /// Returns the atlas UV of a position on a face for the given light style slot.
+    pub fn get_uv(
+        &self,
+        face_index: usize,
+        style_slot: usize,
+        texture_info: &BspTextureInfo,
+        position: [f32; 3],
+    ) -> Option<[f32; 2]> {
+        let entry = self.entries.get(face_index)?.as_ref()?;
+        let rect = entry.rects.get(style_slot)?;
+        let [u, v] = entry.extents.get_luxel_coords(texture_info, position);
+        Some([
+            (rect.x as f32 + u) / self.image.width() as f32,
+            (rect.y as f32 + v) / self.image.height() as f32,
+        ])
+    }
+}
+
+impl BspReader<'_> {
+    /// Computes the texture space extents of a face the same way the engine does.
+    /// Returns `None` for faces with broken references, or extents too large for
+    /// any lightmap.
+    pub fn get_face_extents(&self, face: &BspFace) -> Option<BspFaceExtents> {
+        let texture_info = self.read_texture_infos().get(face.texture_info as usize)?;
+        let positions = self.get_face_vertices(face)?;
+        if positions.is_empty() {
+            return None;
+        }
+
+        let mut mins = [f64::MAX; 2];
+        let mut maxs = [f64::MIN; 2];
+        for position in positions {
+            let position = position.map(|x| x as f64);
+            let axes = [
+                (texture_info.s, texture_info.s_shift),
+                (texture_info.t, texture_info.t_shift),
+            ];
+            for (i, (axis, shift)) in axes.iter().enumerate() {
+                let value = position[0] * axis[0] as f64
+                    + position[1] * axis[1] as f64
+                    + position[2] * axis[2] as f64
+                    + *shift as f64;
+                mins[i] = mins[i].min(value);
+                maxs[i] = maxs[i].max(value);
+            }
+        }
+
+        // Corrupt vertices can put faces anywhere, so the luxel math is checked
+        let luxel_size = LUXEL_SIZE as f64;
+        let mut texture_mins = [0; 2];
+        let mut extents = [0; 2];
+        for i in 0..2 {
+            if !mins[i].is_finite() || !maxs[i].is_finite() {
+                return None;
+            }
+            let block_min = (mins[i] / luxel_size).floor() as i64;
+            let block_max = (maxs[i] / luxel_size).ceil() as i64;
+            let extent = block_max
+                .checked_sub(block_min)?
+                .checked_mul(LUXEL_SIZE as i64)?;
+            if extent > MAX_FACE_EXTENT {
+                return None;
+            }
+            texture_mins[i] = i32::try_from(block_min.checked_mul(LUXEL_SIZE as i64)?).ok()?;
+            extents[i] = extent as i32;
+        }
+        Some(BspFaceExtents {
+            texture_mins,
+            extents,
+        })
+    }
+
+    /// Returns the lightmap data of a face split into its light styles, or `None`
+    /// if the face isn't lit or its lightmap is out of bounds.
+    pub fn get_face_lightmap(&self, face_index: usize) -> Option<BspFaceLightmap<'_>> {
+        let face = self.read_faces().get(face_index)?;
+        if face.lightmap_offset < 0 {
+            return None;
+        }
+        let extents = self.get_face_extents(face)?;
+        let width = extents.lightmap_width();
+        let height = extents.lightmap_height();
+        let luxel_size = self.variant().lightmap_luxel_size();
+        let style_len = (width as usize)
+            .checked_mul(height as usize)?
+            .checked_mul(luxel_size)?;
+        if style_len == 0 {
+            return None;
+        }
+
+        let lighting = self.read_lighting_data();
+        let mut offset = face.lightmap_offset as usize;
+        let mut styles = Vec::with_capacity(MAX_LIGHT_STYLES);
+        for style in face.styles {
+            if style == NO_LIGHT_STYLE {
+                break;
+            }
+            let data = lighting.get(offset..offset.checked_add(style_len)?)?;
+            let data = if luxel_size == 1 {
+                Cow::Owned(data.iter().flat_map(|x| [*x; 3]).collect())
+            } else {
+                Cow::Borrowed(data)
+            };
+            styles.push(BspLightmapStyle { style, data });
+            offset = offset.checked_add(style_len)?;
+        }
+        if styles.is_empty() {
+            return None;
+        }
+
+        Some(BspFaceLightmap {
+            width,
+            height,
+            extents,
+            styles,
+        })
+    }
+
+    /// Packs the lightmaps of every face, for every light style, into one image.
+    pub fn build_lightmap_atlas(&self) -> BspLightmapAtlas {
+        let padding = BspLightmapAtlas::PADDING;
+        let faces = self.read_faces();
+        let lightmaps: Vec<_> = (0..faces.len())
+            .map(|i| self.get_face_lightmap(i))
+            .collect();
+
+        // Sort tallest first and pack into shelves
+        let mut blocks = Vec::new();
+        let mut total_area = 0u64;
+        let mut max_width = 0;
+        for (face_index, lightmap) in lightmaps.iter().enumerate() {
+            if let Some(lightmap) = lightmap {
+                let width = lightmap.width + padding * 2;
+                let height = lightmap.height + padding * 2;
+                for style_slot in 0..lightmap.styles.len() {
+                    blocks.push((face_index, style_slot, width, height));
+                    total_area += width as u64 * height as u64;
+                }
+                max_width = max_width.max(width);
+            }
+        }
+        blocks.sort_by(|a, b| b.3.cmp(&a.3).then(b.2.cmp(&a.2)));
+
+        let atlas_width = ((total_area as f64).sqrt().ceil() as u32)
+            .max(max_width)
+            .max(1)
+            .next_power_of_two();
+        let mut positions = vec![(0, 0); blocks.len()];
+        let mut shelf_x = 0;
+        let mut shelf_y = 0;
+        let mut shelf_height = 0;
+        for (i, (_, _, width, height)) in blocks.iter().enumerate() {
+            if shelf_x + width > atlas_width {
+                shelf_y += shelf_height;
+                shelf_x = 0;
+                shelf_height = 0;
+            }
+            positions[i] = (shelf_x, shelf_y);
+            shelf_x += width;
+            shelf_height = shelf_height.max(*height);
+        }
+        let atlas_height = (shelf_y + shelf_height).max(1);
+
+        let mut image =
+            image::ImageBuffer::<image::Rgb<u8>, Vec<u8>>::new(atlas_width, atlas_height);
+        let mut entries: Vec<Option<BspLightmapAtlasEntry>> = lightmaps
+            .iter()
+            .map(|lightmap| {
+                lightmap.as_ref().map(|lightmap| BspLightmapAtlasEntry {
+                    width: lightmap.width,
+                    height: lightmap.height,
+                    extents: lightmap.extents,
+                    rects: Vec::with_capacity(lightmap.styles.len()),
+                })
+            })
+            .collect();
+        // Blocks were sorted, so place rects in style order afterwards
+        let mut placed = vec![Vec::new(); faces.len()];
+        for ((face_index, style_slot, _, _), (x, y)) in blocks.iter().zip(positions) {
+            let lightmap = lightmaps[*face_index].as_ref().unwrap();
+            let style = &lightmap.styles[*style_slot];
+            copy_padded(
+                &mut image,
+                lightmap.width,
+                lightmap.height,
+                &style.data,
+                x,
+                y,
+                padding,
+            );
+            placed[*face_index].push((
+                *style_slot,
+                BspAtlasRect {
+                    style: style.style,
+                    x: x + padding,
+                    y: y + padding,
+                },
+            ));
+        }
+        for (entry, mut rects) in entries.iter_mut().zip(placed) {
+            if let Some(entry) = entry {
+                rects.sort_by_key(|(style_slot, _)| *style_slot);
+                entry.rects = rects.into_iter().map(|(_, rect)| rect).collect();
+            }
+        }
+
+        BspLightmapAtlas { image, entries }
+    }
+}
+
+fn copy_padded(
+    image: &mut image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    x: u32,
+    y: u32,
+    padding: u32,
+) {
+    for dest_y in 0..height + padding * 2 {
+        let source_y = dest_y.saturating_sub(padding).min(height - 1);
+        for dest_x in 0..width + padding * 2 {
+            let source_x = dest_x.saturating_sub(padding).min(width - 1);
+            let offset = ((source_y * width + source_x) * 3) as usize;
+            let pixel = image::Rgb([data[offset], data[offset + 1], data[offset + 2]]);
+            image.put_pixel(x + dest_x, y + dest_y, pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+
+    #[test]
+    fn face_extents() {
+        let reader = test_maps::read(&test_maps::single_face_map(8.0));
+        let face = &reader.read_faces()[0];
+        let extents = reader.get_face_extents(face).unwrap();
+        assert_eq!(extents.texture_mins, [-16, -16]);
+        assert_eq!(extents.extents, [32, 32]);
+        let lightmap = reader.get_face_lightmap(0).unwrap();
+        assert_eq!((lightmap.width, lightmap.height), (3, 3));
+        assert_eq!(lightmap.styles[0].data.len(), 27);
+    }
+
+    #[test]
+    fn hostile_vertices() {
+        let reader = test_maps::read(&test_maps::single_face_map(1e30));
+        let face = &reader.read_faces()[0];
+        assert!(reader.get_face_extents(face).is_none());
+        assert!(reader.get_face_lightmap(0).is_none());
+        let atlas = reader.build_lightmap_atlas();
+        assert!(atlas.entries[0].is_none());
+    }
+}
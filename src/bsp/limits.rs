@@ -0,0 +1,414 @@
+use crate::util::resolve_map_entity_string;
+
+use super::{
+    BspEntity, BspFace, BspReader, BspTextureKind, LUMP_ENTITIES, LUMP_LIGHTING, LUMP_TEXTURES,
+    LUMP_VISIBILITY,
+};
+
+// Lightmaps are packed into 128x128 textures by the engine's AllocBlock, which
+// gives up after 64 of them.
+const LIGHTMAP_BLOCK_SIZE: usize = 128;
+const MAX_LIGHTMAP_BLOCKS: usize = 64;
+
+/// The largest texture space extent, in texels, a lit face may have before the
+/// engine refuses to load the map with "Bad surface extents".
+pub const MAX_SURFACE_EXTENT: i32 = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspLimitKind {
+    Models,
+    Entities,
+    EntityData,
+    Planes,
+    Nodes,
+    ClipNodes,
+    Leaves,
+    Vertices,
+    Faces,
+    MarkSurfaces,
+    TextureInfos,
+    Edges,
+    SurfaceEdges,
+    Textures,
+    TextureData,
+    Lighting,
+    Visibility,
+    /// 128x128 lightmap blocks the engine allocates at load time.
+    AllocBlock,
+    /// Lit faces whose extents exceed `MAX_SURFACE_EXTENT` or can't be computed.
+    SurfaceExtents,
+}
+
+impl BspLimitKind {
+    pub const ALL: [BspLimitKind; 19] = [
+        BspLimitKind::Models,
+        BspLimitKind::Entities,
+        BspLimitKind::EntityData,
+        BspLimitKind::Planes,
+        BspLimitKind::Nodes,
+        BspLimitKind::ClipNodes,
+        BspLimitKind::Leaves,
+        BspLimitKind::Vertices,
+        BspLimitKind::Faces,
+        BspLimitKind::MarkSurfaces,
+        BspLimitKind::TextureInfos,
+        BspLimitKind::Edges,
+        BspLimitKind::SurfaceEdges,
+        BspLimitKind::Textures,
+        BspLimitKind::TextureData,
+        BspLimitKind::Lighting,
+        BspLimitKind::Visibility,
+        BspLimitKind::AllocBlock,
+        BspLimitKind::SurfaceExtents,
+    ];
+
+    /// The maximum from the Half-Life SDK's bspfile.h, except for models which
+    /// uses the engine's limit. Sizes are in bytes.
+    pub fn max(&self) -> usize {
+        match self {
+            BspLimitKind::Models => 512,
+            BspLimitKind::Entities => 1024,
+            BspLimitKind::EntityData => 128 * 1024,
+            BspLimitKind::Planes => 32767,
+            BspLimitKind::Nodes => 32767,
+            BspLimitKind::ClipNodes => 32767,
+            BspLimitKind::Leaves => 8192,
+            BspLimitKind::Vertices => 65535,
+            BspLimitKind::Faces => 65535,
+            BspLimitKind::MarkSurfaces => 65535,
+            BspLimitKind::TextureInfos => 8192,
+            BspLimitKind::Edges => 256000,
+            BspLimitKind::SurfaceEdges => 512000,
+            BspLimitKind::Textures => 512,
+            BspLimitKind::TextureData => 0x200000,
+            BspLimitKind::Lighting => 0x200000,
+            BspLimitKind::Visibility => 0x200000,
+            BspLimitKind::AllocBlock => MAX_LIGHTMAP_BLOCKS,
+            BspLimitKind::SurfaceExtents => 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BspLimitKind::Models => "models",
+            BspLimitKind::Entities => "entities",
+            BspLimitKind::EntityData => "entdata",
+            BspLimitKind::Planes => "planes",
+            BspLimitKind::Nodes => "nodes",
+            BspLimitKind::ClipNodes => "clipnodes",
+            BspLimitKind::Leaves => "leaves",
+            BspLimitKind::Vertices => "vertexes",
+            BspLimitKind::Faces => "faces",
+            BspLimitKind::MarkSurfaces => "marksurfaces",
+            BspLimitKind::TextureInfos => "texinfos",
+            BspLimitKind::Edges => "edges",
+            BspLimitKind::SurfaceEdges => "surfedges",
+            BspLimitKind::Textures => "textures",
+            BspLimitKind::TextureData => "texdata",
+            BspLimitKind::Lighting => "lightdata",
+            BspLimitKind::Visibility => "visdata",
+            BspLimitKind::AllocBlock => "AllocBlock",
+            BspLimitKind::SurfaceExtents => "bad extents",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BspLimit {
+    pub kind: BspLimitKind,
+    pub used: usize,
+    pub max: usize,
+    /// Face indices that fail a per-face check: faces with bad extents, or
+    /// faces whose lightmap didn't fit in any AllocBlock.
+    pub offenders: Vec<usize>,
+}
+
+impl BspLimit {
+    fn new(kind: BspLimitKind, used: usize) -> Self {
+        Self {
+            kind,
+            used,
+            max: kind.max(),
+            offenders: Vec::new(),
+        }
+    }
+
+    /// Returns the usage as a percentage of the maximum. Limits with a maximum
+    /// of zero are either at 0% or 100%.
+    pub fn percent(&self) -> f64 {
+        if self.max == 0 {
+            if self.used == 0 { 0.0 } else { 100.0 }
+        } else {
+            self.used as f64 / self.max as f64 * 100.0
+        }
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used > self.max
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BspLimitReport {
+    pub limits: Vec<BspLimit>,
+}
+
+impl BspLimitReport {
+    pub fn get(&self, kind: BspLimitKind) -> Option<&BspLimit> {
+        self.limits.iter().find(|x| x.kind == kind)
+    }
+
+    pub fn exceeded(&self) -> impl Iterator<Item = &BspLimit> {
+        self.limits.iter().filter(|x| x.is_exceeded())
+    }
+}
+
+impl std::fmt::Display for BspLimitReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<14} {:>9} / {:<9} {:>8}",
+            "Object", "Used", "Max", "Fullness"
+        )?;
+        for limit in &self.limits {
+            writeln!(
+                f,
+                "{:<14} {:>9} / {:<9} {:>7.1}%{}",
+                limit.kind.name(),
+                limit.used,
+                limit.max,
+                limit.percent(),
+                if limit.is_exceeded() {
+                    "  EXCEEDED"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl BspReader<'_> {
+    /// Measures the map against the compiler and engine limits. The entity count
+    /// is left out if the entity lump doesn't parse.
+    pub fn check_limits(&self) -> BspLimitReport {
+        let mut limits = Vec::new();
+        limits.push(BspLimit::new(
+            BspLimitKind::Models,
+            self.read_models().len(),
+        ));
+        let entity_string = resolve_map_entity_string(self);
+        if let Ok(entities) = BspEntity::parse_entities(&entity_string) {
+            limits.push(BspLimit::new(BspLimitKind::Entities, entities.len()));
+        }
+        limits.push(BspLimit::new(
+            BspLimitKind::EntityData,
+            self.header().lumps[LUMP_ENTITIES].len as usize,
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::Planes,
+            self.read_planes().len(),
+        ));
+        limits.push(BspLimit::new(BspLimitKind::Nodes, self.read_nodes().len()));
+        limits.push(BspLimit::new(
+            BspLimitKind::ClipNodes,
+            self.read_clip_nodes().len(),
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::Leaves,
+            self.read_leaves().len(),
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::Vertices,
+            self.read_vertices().len(),
+        ));
+        limits.push(BspLimit::new(BspLimitKind::Faces, self.read_faces().len()));
+        limits.push(BspLimit::new(
+            BspLimitKind::MarkSurfaces,
+            self.read_mark_surfaces().len(),
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::TextureInfos,
+            self.read_texture_infos().len(),
+        ));
+        limits.push(BspLimit::new(BspLimitKind::Edges, self.read_edges().len()));
+        limits.push(BspLimit::new(
+            BspLimitKind::SurfaceEdges,
+            self.read_surface_edges().len(),
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::Textures,
+            self.read_textures().len(),
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::TextureData,
+            self.header().lumps[LUMP_TEXTURES].len as usize,
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::Lighting,
+            self.header().lumps[LUMP_LIGHTING].len as usize,
+        ));
+        limits.push(BspLimit::new(
+            BspLimitKind::Visibility,
+            self.header().lumps[LUMP_VISIBILITY].len as usize,
+        ));
+        limits.push(self.check_alloc_block());
+        limits.push(self.check_surface_extents());
+        BspLimitReport { limits }
+    }
+
+    // Returns the kind of the texture a face uses, treating missing textures as normal.
+    fn get_face_texture_kind(&self, face: &BspFace) -> BspTextureKind {
+        self.read_texture_infos()
+            .get(face.texture_info as usize)
+            .and_then(|texture_info| {
+                self.read_textures()
+                    .get(texture_info.texture_index as usize)
+            })
+            .map(|texture| texture.kind())
+            .unwrap_or(BspTextureKind::Normal)
+    }
+
+    // Replays the engine's lightmap allocation (GL_CreateSurfaceLightmap), which
+    // skips sky and liquid surfaces and visits faces model by model.
+    fn check_alloc_block(&self) -> BspLimit {
+        let faces = self.read_faces();
+        let mut blocks: Vec<[usize; LIGHTMAP_BLOCK_SIZE]> = Vec::new();
+        let mut offenders = Vec::new();
+        for model in self.read_models() {
+            let first_face = (model.first_face.max(0) as usize).min(faces.len());
+            let last_face = first_face
+                .saturating_add(model.faces.max(0) as usize)
+                .min(faces.len());
+            for (face_index, face) in faces[first_face..last_face].iter().enumerate() {
+                let face_index = first_face + face_index;
+                let kind = self.get_face_texture_kind(face);
+                if kind == BspTextureKind::Sky || kind == BspTextureKind::Liquid {
+                    continue;
+                }
+                let Some(extents) = self.get_face_extents(face) else {
+                    continue;
+                };
+                let width = extents.lightmap_width() as usize;
+                let height = extents.lightmap_height() as usize;
+                if !alloc_block(&mut blocks, width, height) {
+                    offenders.push(face_index);
+                }
+            }
+        }
+
+        let mut limit = BspLimit::new(BspLimitKind::AllocBlock, blocks.len());
+        if !offenders.is_empty() {
+            // Count the blocks the engine would have needed, one past the last
+            limit.used = limit.used.max(MAX_LIGHTMAP_BLOCKS + 1);
+        }
+        limit.offenders = offenders;
+        limit
+    }
+
+    fn check_surface_extents(&self) -> BspLimit {
+        let textures = self.read_textures();
+        let texture_infos = self.read_texture_infos();
+        let mut offenders = Vec::new();
+        for (face_index, face) in self.read_faces().iter().enumerate() {
+            let Some(texture_info) = texture_infos.get(face.texture_info as usize) else {
+                continue;
+            };
+            // The engine doesn't check faces it never lightmaps
+            if texture_info.is_special() {
+                continue;
+            }
+            let is_sky = textures
+                .get(texture_info.texture_index as usize)
+                .is_some_and(|x| x.kind() == BspTextureKind::Sky);
+            if is_sky {
+                continue;
+            }
+            // Faces too large to compute extents for are bad extents too
+            let is_bad = self
+                .get_face_extents(face)
+                .is_none_or(|x| x.extents.iter().any(|x| *x > MAX_SURFACE_EXTENT));
+            if is_bad {
+                offenders.push(face_index);
+            }
+        }
+
+        let mut limit = BspLimit::new(BspLimitKind::SurfaceExtents, offenders.len());
+        limit.offenders = offenders;
+        limit
+    }
+}
+
+// A skyline allocator over up to MAX_LIGHTMAP_BLOCKS blocks, returning false if
+// the lightmap doesn't fit anywhere.
+fn alloc_block(
+    blocks: &mut Vec<[usize; LIGHTMAP_BLOCK_SIZE]>,
+    width: usize,
+    height: usize,
+) -> bool {
+    if width > LIGHTMAP_BLOCK_SIZE || height > LIGHTMAP_BLOCK_SIZE {
+        return false;
+    }
+    for block_index in 0..MAX_LIGHTMAP_BLOCKS {
+        if block_index == blocks.len() {
+            blocks.push([0; LIGHTMAP_BLOCK_SIZE]);
+        }
+        let allocated = &mut blocks[block_index];
+
+        let mut best = LIGHTMAP_BLOCK_SIZE;
+        let mut x = 0;
+        for i in 0..LIGHTMAP_BLOCK_SIZE - width {
+            let mut best2 = 0;
+            let mut fits = true;
+            for column in &allocated[i..i + width] {
+                if *column >= best {
+                    fits = false;
+                    break;
+                }
+                best2 = best2.max(*column);
+            }
+            if fits {
+                x = i;
+                best = best2;
+            }
+        }
+
+        if best + height > LIGHTMAP_BLOCK_SIZE {
+            continue;
+        }
+        for column in &mut allocated[x..x + width] {
+            *column = best + height;
+        }
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+    use super::*;
+
+    #[test]
+    fn surface_extents() {
+        let reader = test_maps::read(&test_maps::single_face_map(8.0));
+        let report = reader.check_limits();
+        let limit = report.get(BspLimitKind::SurfaceExtents).unwrap();
+        assert!(limit.offenders.is_empty());
+
+        let reader = test_maps::read(&test_maps::single_face_map(1024.0));
+        let report = reader.check_limits();
+        let limit = report.get(BspLimitKind::SurfaceExtents).unwrap();
+        assert_eq!(limit.offenders, [0]);
+    }
+
+    #[test]
+    fn hostile_vertices() {
+        let reader = test_maps::read(&test_maps::single_face_map(1e30));
+        let report = reader.check_limits();
+        let limit = report.get(BspLimitKind::SurfaceExtents).unwrap();
+        assert_eq!(limit.offenders, [0]);
+        assert!(limit.is_exceeded());
+    }
+}
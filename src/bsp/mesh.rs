@@ -0,0 +1,177 @@
+use super::{BspFace, BspReader, BspTextureInfo};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BspMeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// The vertices of a single bsp face within a `BspTextureMesh`.
+#[derive(Copy, Clone, Debug)]
+pub struct BspMeshFace {
+    pub face_index: usize,
+    pub first_vertex: usize,
+    pub vertices: usize,
+}
+
+/// All the faces of a model that use the same texture, triangulated as fans.
+#[derive(Clone, Debug)]
+pub struct BspTextureMesh {
+    pub texture_index: u32,
+    pub vertices: Vec<BspMeshVertex>,
+    pub indices: Vec<u32>,
+    pub faces: Vec<BspMeshFace>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BspModelMesh {
+    pub model_index: usize,
+    pub meshes: Vec<BspTextureMesh>,
+}
+
+impl BspReader<'_> {
+    /// Returns the vertex positions of a face in winding order, or `None` if the
+    /// face references edges or vertices that don't exist.
+    pub fn get_face_vertices(&self, face: &BspFace) -> Option<Vec<[f32; 3]>> {
+        let surface_edges = self.read_surface_edges();
+        let edges = self.read_edges();
+        let vertices = self.read_vertices();
+
+        let first_edge = face.first_edge as usize;
+        let face_edges = surface_edges.get(first_edge..first_edge + face.edges as usize)?;
+        let mut positions = Vec::with_capacity(face_edges.len());
+        for surface_edge in face_edges {
+            let edge_index = surface_edge.0;
+            let vertex_index = if edge_index >= 0 {
+                edges.get(edge_index as usize)?.vertices[0]
+            } else {
+                edges.get(edge_index.unsigned_abs() as usize)?.vertices[1]
+            };
+            positions.push(vertices.get(vertex_index as usize)?.to_array());
+        }
+        Some(positions)
+    }
+
+    /// Returns the normal of a face, taking which side of its plane it's on into account.
+    pub fn get_face_normal(&self, face: &BspFace) -> Option<[f32; 3]> {
+        let plane = self.read_planes().get(face.plane as usize)?;
+        let normal = plane.normal;
+        if face.plane_side != 0 {
+            Some([-normal[0], -normal[1], -normal[2]])
+        } else {
+            Some(normal)
+        }
+    }
+
+    /// Builds meshes for every model in the map. Model 0 is the world.
+    pub fn build_model_meshes(&self) -> Vec<BspModelMesh> {
+        (0..self.read_models().len())
+            .filter_map(|model_index| self.build_model_mesh(model_index))
+            .collect()
+    }
+
+    /// Builds a mesh for each texture used by the given model. Faces that
+    /// reference missing data are skipped.
+    pub fn build_model_mesh(&self, model_index: usize) -> Option<BspModelMesh> {
+        let model = self.read_models().get(model_index)?;
+        let faces = self.read_faces();
+        let texture_infos = self.read_texture_infos();
+        let texture_sizes = self.get_texture_sizes();
+
+        let first_face = model.first_face.max(0) as usize;
+        let last_face = first_face + model.faces.max(0) as usize;
+        let model_faces = faces.get(first_face..last_face)?;
+
+        let mut meshes: Vec<BspTextureMesh> = Vec::new();
+        for (i, face) in model_faces.iter().enumerate() {
+            let face_index = first_face + i;
+            let Some(texture_info) = texture_infos.get(face.texture_info as usize) else {
+                continue;
+            };
+            let (Some(positions), Some(normal)) =
+                (self.get_face_vertices(face), self.get_face_normal(face))
+            else {
+                continue;
+            };
+            if positions.len() < 3 {
+                continue;
+            }
+
+            let texture_index = texture_info.texture_index;
+            let mesh_index = if let Some(mesh_index) =
+                meshes.iter().position(|x| x.texture_index == texture_index)
+            {
+                mesh_index
+            } else {
+                meshes.push(BspTextureMesh {
+                    texture_index,
+                    vertices: Vec::new(),
+                    indices: Vec::new(),
+                    faces: Vec::new(),
+                });
+                meshes.len() - 1
+            };
+            let mesh = &mut meshes[mesh_index];
+
+            let texture_size = texture_sizes
+                .get(texture_index as usize)
+                .copied()
+                .flatten()
+                .unwrap_or((1, 1));
+            let first_vertex = mesh.vertices.len();
+            for position in &positions {
+                mesh.vertices.push(BspMeshVertex {
+                    position: *position,
+                    normal,
+                    uv: texture_info.get_uv(*position, texture_size),
+                });
+            }
+            for j in 1..positions.len() - 1 {
+                mesh.indices.push(first_vertex as u32);
+                mesh.indices.push((first_vertex + j) as u32);
+                mesh.indices.push((first_vertex + j + 1) as u32);
+            }
+            mesh.faces.push(BspMeshFace {
+                face_index,
+                first_vertex,
+                vertices: positions.len(),
+            });
+        }
+
+        Some(BspModelMesh {
+            model_index,
+            meshes,
+        })
+    }
+
+    fn get_texture_sizes(&self) -> Vec<Option<(u32, u32)>> {
+        let textures = self.read_textures();
+        (0..textures.len())
+            .map(|i| {
+                let texture = textures.get(i)?;
+                let header = texture.header();
+                if header.width == 0 || header.height == 0 {
+                    None
+                } else {
+                    Some((header.width, header.height))
+                }
+            })
+            .collect()
+    }
+}
+
+impl BspTextureInfo {
+    /// Projects a position onto the s/t axes, in texels.
+    pub fn get_texel_coords(&self, position: [f32; 3]) -> [f32; 2] {
+        let s = position[0] * self.s[0] + position[1] * self.s[1] + position[2] * self.s[2];
+        let t = position[0] * self.t[0] + position[1] * self.t[1] + position[2] * self.t[2];
+        [s + self.s_shift, t + self.t_shift]
+    }
+
+    /// Projects a position onto the s/t axes and normalizes by the texture size.
+    pub fn get_uv(&self, position: [f32; 3], texture_size: (u32, u32)) -> [f32; 2] {
+        let [s, t] = self.get_texel_coords(position);
+        [s / texture_size.0 as f32, t / texture_size.1 as f32]
+    }
+}
@@ -0,0 +1,312 @@
+use crate::util::resolve_map_entity_string;
+
+use super::{BspEntity, BspEntityParseError, BspReader};
+
+// Brush entities that rotate around their origin brush while the game runs
+const ROTATING_CLASSNAMES: [&str; 7] = [
+    "func_rotating",
+    "func_door_rotating",
+    "func_pendulum",
+    "func_rot_button",
+    "momentary_rot_button",
+    "func_tracktrain",
+    "func_vehicle",
+];
+
+// Brush entities whose "angles" only set a movement direction. The game clears
+// their angles on spawn (SetMovedir), so the model is never rotated.
+const MOVEDIR_CLASSNAMES: [&str; 5] = [
+    "func_door",
+    "func_water",
+    "func_button",
+    "momentary_door",
+    "func_conveyor",
+];
+
+/// Where a brush model is placed in the world. Angles are pitch, yaw and roll in
+/// degrees, applied the way the engine's physics does (AngleVectors).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BspTransform {
+    pub origin: [f32; 3],
+    pub angles: [f32; 3],
+}
+
+impl BspTransform {
+    pub const IDENTITY: BspTransform = BspTransform {
+        origin: [0.0; 3],
+        angles: [0.0; 3],
+    };
+
+    /// Reads the transform of a brush entity. The single "angle" key is handled
+    /// like the engine does, with -1 meaning up and -2 meaning down.
+    pub fn from_entity(entity: &BspEntity) -> Self {
+        let origin = entity.get_vector("origin").unwrap_or([0.0; 3]);
+        let mut angles = entity.get_vector("angles").unwrap_or([0.0; 3]);
+        if let Some(angle) = entity
+            .get("angle")
+            .and_then(|x| x.trim().parse::<f32>().ok())
+        {
+            angles = if angle >= 0.0 {
+                [angles[0], angle, angles[2]]
+            } else if angle as i32 == -1 {
+                [-90.0, 0.0, 0.0]
+            } else {
+                [90.0, 0.0, 0.0]
+            };
+        }
+
+        let classname = entity.classname().unwrap_or("");
+        if MOVEDIR_CLASSNAMES.contains(&classname) || classname.starts_with("trigger_") {
+            angles = [0.0; 3];
+        }
+        Self { origin, angles }
+    }
+
+    /// Returns the model's forward, left and up axes in world space.
+    pub fn axes(&self) -> [[f32; 3]; 3] {
+        let [pitch, yaw, roll] = self.angles.map(|x| x.to_radians());
+        let (sp, cp) = pitch.sin_cos();
+        let (sy, cy) = yaw.sin_cos();
+        let (sr, cr) = roll.sin_cos();
+        let forward = [cp * cy, cp * sy, -sp];
+        // AngleVectors returns right, the model's y axis points the other way
+        let left = [sr * sp * cy - cr * sy, sr * sp * sy + cr * cy, sr * cp];
+        let up = [cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp];
+        [forward, left, up]
+    }
+
+    /// Returns the rotation as a quaternion (x, y, z, w).
+    pub fn rotation_quaternion(&self) -> [f32; 4] {
+        let [pitch, yaw, roll] = self.angles.map(|x| (x * 0.5).to_radians());
+        let (sp, cp) = pitch.sin_cos();
+        let (sy, cy) = yaw.sin_cos();
+        let (sr, cr) = roll.sin_cos();
+        [
+            sr * cp * cy - cr * sp * sy,
+            cr * sp * cy + sr * cp * sy,
+            cr * cp * sy - sr * sp * cy,
+            cr * cp * cy + sr * sp * sy,
+        ]
+    }
+
+    pub fn is_rotated(&self) -> bool {
+        self.angles != [0.0; 3]
+    }
+
+    /// Transforms a point from model space to world space.
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let axes = self.axes();
+        let mut result = self.origin;
+        for (axis, value) in axes.iter().zip(point) {
+            for i in 0..3 {
+                result[i] += axis[i] * value;
+            }
+        }
+        result
+    }
+
+    /// Transforms a point from world space to model space.
+    pub fn inverse_transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let relative = [
+            point[0] - self.origin[0],
+            point[1] - self.origin[1],
+            point[2] - self.origin[2],
+        ];
+        self.axes()
+            .map(|axis| axis[0] * relative[0] + axis[1] * relative[1] + axis[2] * relative[2])
+    }
+
+    /// Returns the world space AABB of a model space AABB.
+    pub fn transform_bounds(&self, mins: [f32; 3], maxs: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+        let mut world_mins = [f32::MAX; 3];
+        let mut world_maxs = [f32::MIN; 3];
+        for point in box_corners(mins, maxs) {
+            let point = self.transform_point(point);
+            for i in 0..3 {
+                world_mins[i] = world_mins[i].min(point[i]);
+                world_maxs[i] = world_maxs[i].max(point[i]);
+            }
+        }
+        (world_mins, world_maxs)
+    }
+}
+
+/// A brush model and the entity that places it.
+#[derive(Clone, Debug)]
+pub struct BspModelInstance {
+    pub model_index: usize,
+    /// `None` for models no entity references.
+    pub entity_index: Option<usize>,
+    pub transform: BspTransform,
+    /// World space bounds at spawn.
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    /// True for entities that rotate around their origin at runtime.
+    pub rotating: bool,
+    // Model space bounds, used for the swept bounds of rotating entities
+    model_mins: [f32; 3],
+    model_maxs: [f32; 3],
+}
+
+impl BspModelInstance {
+    /// Returns bounds that cover every position the model can reach by rotating.
+    /// Like the engine, rotating models get a cube around their origin sized by
+    /// the model's radius. Other models return their spawn bounds.
+    pub fn swept_bounds(&self) -> ([f32; 3], [f32; 3]) {
+        if !self.rotating && !self.transform.is_rotated() {
+            return (self.mins, self.maxs);
+        }
+        let radius_squared = box_corners(self.model_mins, self.model_maxs)
+            .iter()
+            .map(|point| point.iter().map(|x| x * x).sum::<f32>())
+            .fold(0.0, f32::max);
+        let radius = radius_squared.sqrt();
+        let origin = self.transform.origin;
+        (origin.map(|x| x - radius), origin.map(|x| x + radius))
+    }
+
+    pub fn overlaps(&self, mins: [f32; 3], maxs: [f32; 3]) -> bool {
+        (0..3).all(|i| self.mins[i] <= maxs[i] && self.maxs[i] >= mins[i])
+    }
+
+    pub fn contains_point(&self, point: [f32; 3]) -> bool {
+        self.overlaps(point, point)
+    }
+}
+
+/// Every brush model in a map paired with the entity that uses it.
+#[derive(Clone, Debug)]
+pub struct BspModelInstances {
+    /// One instance per entity with a brush model, followed by one for each
+    /// model no entity references. Model 0 is paired with worldspawn.
+    pub instances: Vec<BspModelInstance>,
+    /// Entities whose "model" key names a brush model that doesn't exist.
+    pub unresolved_entities: Vec<usize>,
+}
+
+impl BspModelInstances {
+    pub fn new(reader: &BspReader, entities: &[BspEntity]) -> Self {
+        let models = reader.read_models();
+        let mut instances = Vec::new();
+        let mut unresolved_entities = Vec::new();
+        let mut used_models = vec![false; models.len()];
+        for (entity_index, entity) in entities.iter().enumerate() {
+            let model_index = if entity.classname() == Some("worldspawn") {
+                0
+            } else if entity.get("model").is_some_and(|x| x.starts_with('*')) {
+                match entity.get_model_index() {
+                    Some(model_index) if model_index < models.len() => model_index,
+                    _ => {
+                        unresolved_entities.push(entity_index);
+                        continue;
+                    }
+                }
+            } else {
+                continue;
+            };
+            if models.is_empty() {
+                continue;
+            }
+
+            used_models[model_index] = true;
+            let transform = if model_index == 0 {
+                BspTransform::IDENTITY
+            } else {
+                BspTransform::from_entity(entity)
+            };
+            let classname = entity.classname().unwrap_or("");
+            instances.push(Self::new_instance(
+                reader,
+                model_index,
+                Some(entity_index),
+                transform,
+                ROTATING_CLASSNAMES.contains(&classname),
+            ));
+        }
+        for (model_index, used) in used_models.iter().enumerate() {
+            if !used {
+                instances.push(Self::new_instance(
+                    reader,
+                    model_index,
+                    None,
+                    BspTransform::IDENTITY,
+                    false,
+                ));
+            }
+        }
+
+        Self {
+            instances,
+            unresolved_entities,
+        }
+    }
+
+    pub fn from_reader(reader: &BspReader) -> Result<Self, BspEntityParseError> {
+        let entity_string = resolve_map_entity_string(reader);
+        let entities = BspEntity::parse_entities(&entity_string)?;
+        Ok(Self::new(reader, &entities))
+    }
+
+    fn new_instance(
+        reader: &BspReader,
+        model_index: usize,
+        entity_index: Option<usize>,
+        transform: BspTransform,
+        rotating: bool,
+    ) -> BspModelInstance {
+        let model = &reader.read_models()[model_index];
+        let (mins, maxs) = transform.transform_bounds(model.mins, model.maxs);
+        BspModelInstance {
+            model_index,
+            entity_index,
+            transform,
+            mins,
+            maxs,
+            rotating,
+            model_mins: model.mins,
+            model_maxs: model.maxs,
+        }
+    }
+
+    pub fn get_by_entity(&self, entity_index: usize) -> Option<&BspModelInstance> {
+        self.instances
+            .iter()
+            .find(|x| x.entity_index == Some(entity_index))
+    }
+
+    /// Returns every instance of a model. Usually there's one, but nothing stops
+    /// several entities from sharing a model.
+    pub fn get_by_model(&self, model_index: usize) -> impl Iterator<Item = &BspModelInstance> {
+        self.instances
+            .iter()
+            .filter(move |x| x.model_index == model_index)
+    }
+
+    /// Returns brush entity instances whose world bounds touch the box. The world
+    /// and models without an entity are skipped.
+    pub fn get_overlapping(
+        &self,
+        mins: [f32; 3],
+        maxs: [f32; 3],
+    ) -> impl Iterator<Item = &BspModelInstance> {
+        self.instances
+            .iter()
+            .filter(|x| x.model_index != 0 && x.entity_index.is_some())
+            .filter(move |x| x.overlaps(mins, maxs))
+    }
+
+    /// Returns brush entity instances whose world bounds contain the point.
+    pub fn get_at_point(&self, point: [f32; 3]) -> impl Iterator<Item = &BspModelInstance> {
+        self.get_overlapping(point, point)
+    }
+}
+
+fn box_corners(mins: [f32; 3], maxs: [f32; 3]) -> [[f32; 3]; 8] {
+    std::array::from_fn(|corner| {
+        [
+            if corner & 1 == 0 { mins[0] } else { maxs[0] },
+            if corner & 2 == 0 { mins[1] } else { maxs[1] },
+            if corner & 4 == 0 { mins[2] } else { maxs[2] },
+        ]
+    })
+}
@@ -0,0 +1,300 @@
+use super::winding::{Winding, base_winding, split_winding, winding_area};
+use super::{BspContents, BspNodeChild, BspReader};
+
+// Windings smaller than this are slivers left over from splitting
+const MIN_PORTAL_AREA: f64 = 0.1;
+// Space left around the world bounds so portals on the outer walls aren't clipped
+const BOUNDS_PADDING: f64 = 8.0;
+
+/// A convex polygon shared by two leaves of the world's render hull.
+#[derive(Clone, Debug)]
+pub struct BspLeafPortal {
+    /// The leaves on the front and back of the portal.
+    pub leaves: [usize; 2],
+    /// Points from the back leaf into the front leaf.
+    pub normal: [f32; 3],
+    pub winding: Vec<[f32; 3]>,
+    pub area: f32,
+}
+
+/// Which leaves of the world touch each other, built the way the compilers build
+/// portals for vis: every node's plane is clipped to the node's region and split
+/// down the tree until it separates two leaves.
+#[derive(Clone, Debug)]
+pub struct BspLeafGraph {
+    pub portals: Vec<BspLeafPortal>,
+    contents: Vec<Option<BspContents>>,
+    // (neighbor leaf, portal index) for each leaf
+    neighbors: Vec<Vec<(usize, usize)>>,
+}
+
+impl BspContents {
+    /// Returns true for contents the player can move through.
+    pub fn is_passable(&self) -> bool {
+        matches!(
+            self,
+            BspContents::Empty
+                | BspContents::Water
+                | BspContents::Slime
+                | BspContents::Lava
+                | BspContents::Current0
+                | BspContents::Current90
+                | BspContents::Current180
+                | BspContents::Current270
+                | BspContents::CurrentUp
+                | BspContents::CurrentDown
+        )
+    }
+}
+
+impl BspLeafGraph {
+    /// Returns the leaves touching a leaf, with the index of the portal between
+    /// them. A pair of leaves can share more than one portal.
+    pub fn get_neighbors(&self, leaf_index: usize) -> &[(usize, usize)] {
+        self.neighbors
+            .get(leaf_index)
+            .map(|x| x.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the total area of the portals between two leaves.
+    pub fn get_shared_area(&self, leaf_a: usize, leaf_b: usize) -> f32 {
+        self.get_neighbors(leaf_a)
+            .iter()
+            .filter(|(leaf, _)| *leaf == leaf_b)
+            .map(|(_, portal)| self.portals[*portal].area)
+            .fold(0.0, |total, area| total + area)
+    }
+
+    pub fn get_contents(&self, leaf_index: usize) -> Option<BspContents> {
+        self.contents.get(leaf_index).copied().flatten()
+    }
+
+    /// Floods out from the start leaves through leaves whose contents pass the
+    /// filter, returning which leaves were reached. Start leaves that fail the
+    /// filter aren't reached.
+    pub fn flood_fill<F: Fn(BspContents) -> bool>(&self, starts: &[usize], filter: F) -> Vec<bool> {
+        let passes = |leaf: usize| self.get_contents(leaf).is_some_and(&filter);
+        let mut reached = vec![false; self.neighbors.len()];
+        let mut stack = Vec::new();
+        for start in starts {
+            if *start < reached.len() && !reached[*start] && passes(*start) {
+                reached[*start] = true;
+                stack.push(*start);
+            }
+        }
+        while let Some(leaf) = stack.pop() {
+            for (neighbor, _) in &self.neighbors[leaf] {
+                if !reached[*neighbor] && passes(*neighbor) {
+                    reached[*neighbor] = true;
+                    stack.push(*neighbor);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Splits the leaves that pass the filter into connected regions.
+    pub fn find_regions<F: Fn(BspContents) -> bool>(&self, filter: F) -> Vec<Vec<usize>> {
+        let mut assigned = vec![false; self.neighbors.len()];
+        let mut regions = Vec::new();
+        for leaf in 0..self.neighbors.len() {
+            if assigned[leaf] {
+                continue;
+            }
+            let reached = self.flood_fill(&[leaf], &filter);
+            let region: Vec<_> = (0..reached.len()).filter(|x| reached[*x]).collect();
+            if region.is_empty() {
+                continue;
+            }
+            for leaf in &region {
+                assigned[*leaf] = true;
+            }
+            regions.push(region);
+        }
+        regions
+    }
+
+    pub fn is_reachable<F: Fn(BspContents) -> bool>(
+        &self,
+        from: usize,
+        to: usize,
+        filter: F,
+    ) -> bool {
+        self.flood_fill(&[from], filter)
+            .get(to)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Returns the leaves that pass the filter but can't be reached from any of
+    /// the start leaves.
+    pub fn find_unreachable<F: Fn(BspContents) -> bool>(
+        &self,
+        starts: &[usize],
+        filter: F,
+    ) -> Vec<usize> {
+        let reached = self.flood_fill(starts, &filter);
+        (0..reached.len())
+            .filter(|x| !reached[*x] && self.get_contents(*x).is_some_and(&filter))
+            .collect()
+    }
+}
+
+struct Portal {
+    // Node or leaf keys, front side first
+    sides: [usize; 2],
+    normal: [f64; 3],
+    winding: Winding,
+}
+
+impl BspReader<'_> {
+    /// Builds the portals between the leaves of the world's render hull. Leaves of
+    /// other brush models aren't part of the graph. Portals between two solid
+    /// leaves are left out, since every solid leaf is usually leaf 0.
+    pub fn build_leaf_graph(&self) -> BspLeafGraph {
+        let leaves = self.read_leaves();
+        let world = self.read_models().first();
+        let world_leaves = world
+            .map(|x| (x.vis_leaves.max(0) as usize + 1).min(leaves.len()))
+            .unwrap_or(0);
+        let mut graph = BspLeafGraph {
+            portals: Vec::new(),
+            contents: leaves[..world_leaves]
+                .iter()
+                .map(|x| x.try_contents())
+                .collect(),
+            neighbors: vec![Vec::new(); world_leaves],
+        };
+        let Some(world) = world.filter(|x| x.head_nodes[0] >= 0) else {
+            return graph;
+        };
+        let nodes = self.read_nodes();
+        let planes = self.read_planes();
+
+        // Nodes and leaves share one key space, leaves come after nodes
+        let key = |child: BspNodeChild| match child {
+            BspNodeChild::Node(node_index) => node_index,
+            BspNodeChild::Leaf(leaf_index) => nodes.len() + leaf_index,
+        };
+
+        let mut bounds_planes = Vec::new();
+        let mut base_size = 0.0f64;
+        for i in 0..3 {
+            let mut normal = [0.0; 3];
+            normal[i] = 1.0;
+            let min = world.mins[i] as f64 - BOUNDS_PADDING;
+            let max = world.maxs[i] as f64 + BOUNDS_PADDING;
+            bounds_planes.push((normal, min));
+            bounds_planes.push((normal.map(|x| -x), -max));
+            base_size = base_size.max(min.abs()).max(max.abs());
+        }
+        let base_size = base_size * 2.0;
+
+        let mut portals: Vec<Option<Portal>> = Vec::new();
+        let mut attached: Vec<Vec<usize>> = vec![Vec::new(); nodes.len() + leaves.len()];
+        let mut visited = vec![false; nodes.len()];
+        let mut stack = vec![(world.head_nodes[0] as usize, bounds_planes)];
+        while let Some((node_index, clip_planes)) = stack.pop() {
+            // A corrupt tree could share nodes, each node only gets portals once
+            let Some(node) = nodes.get(node_index) else {
+                continue;
+            };
+            let Some(plane) = planes.get(node.plane as usize) else {
+                continue;
+            };
+            if std::mem::replace(&mut visited[node_index], true) {
+                continue;
+            }
+            let normal = plane.normal.map(|x| x as f64);
+            let dist = plane.dist as f64;
+            let front = key(node.child(0));
+            let back = key(node.child(1));
+            if front >= attached.len() || back >= attached.len() {
+                continue;
+            }
+
+            // The portal separating this node's children
+            let mut winding = Some(base_winding(normal, dist, base_size));
+            for (clip_normal, clip_dist) in &clip_planes {
+                let Some(current) = winding else {
+                    break;
+                };
+                winding = split_winding(&current, *clip_normal, *clip_dist).0;
+            }
+            if let Some(winding) = winding.filter(|x| winding_area(x) >= MIN_PORTAL_AREA) {
+                attached[front].push(portals.len());
+                attached[back].push(portals.len());
+                portals.push(Some(Portal {
+                    sides: [front, back],
+                    normal,
+                    winding,
+                }));
+            }
+
+            // Portals on the boundary of this node's region are split between
+            // its children
+            for portal_index in std::mem::take(&mut attached[node_index]) {
+                let Some(portal) = portals[portal_index].take() else {
+                    continue;
+                };
+                let side = if portal.sides[0] == node_index { 0 } else { 1 };
+                let other = portal.sides[side ^ 1];
+                attached[other].retain(|x| *x != portal_index);
+
+                let (front_winding, back_winding) = split_winding(&portal.winding, normal, dist);
+                for (winding, child) in [(front_winding, front), (back_winding, back)] {
+                    let Some(winding) = winding.filter(|x| winding_area(x) >= MIN_PORTAL_AREA)
+                    else {
+                        continue;
+                    };
+                    let mut sides = portal.sides;
+                    sides[side] = child;
+                    attached[child].push(portals.len());
+                    attached[other].push(portals.len());
+                    portals.push(Some(Portal {
+                        sides,
+                        normal: portal.normal,
+                        winding,
+                    }));
+                }
+            }
+
+            for (side, (clip_normal, clip_dist)) in
+                [(0, (normal, dist)), (1, (normal.map(|x| -x), -dist))]
+            {
+                if let BspNodeChild::Node(child_index) = node.child(side) {
+                    let mut child_planes = clip_planes.clone();
+                    child_planes.push((clip_normal, clip_dist));
+                    stack.push((child_index, child_planes));
+                }
+            }
+        }
+
+        for portal in portals.into_iter().flatten() {
+            let [front, back] = portal.sides;
+            if front < nodes.len() || back < nodes.len() {
+                continue;
+            }
+            let front = front - nodes.len();
+            let back = back - nodes.len();
+            if front == back || front >= world_leaves || back >= world_leaves {
+                continue;
+            }
+            let portal_index = graph.portals.len();
+            graph.portals.push(BspLeafPortal {
+                leaves: [front, back],
+                normal: portal.normal.map(|x| x as f32),
+                area: winding_area(&portal.winding) as f32,
+                winding: portal
+                    .winding
+                    .iter()
+                    .map(|point| point.map(|x| x as f32))
+                    .collect(),
+            });
+            graph.neighbors[front].push((back, portal_index));
+            graph.neighbors[back].push((front, portal_index));
+        }
+        graph
+    }
+}
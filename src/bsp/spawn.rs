@@ -0,0 +1,266 @@
+use crate::util::resolve_map_entity_string;
+
+use super::{BspContents, BspEntity, BspEntityParseError, BspHull, BspReader};
+
+const PLAYER_CLASSNAMES: [&str; 3] = [
+    "info_player_start",
+    "info_player_deathmatch",
+    "info_player_coop",
+];
+
+// Players carried across a level change keep their offset from this entity
+const LANDMARK_CLASSNAME: &str = "info_landmark";
+
+// The player's standing box, centered on its origin
+const PLAYER_MINS: [f32; 3] = [-16.0, -16.0, -36.0];
+const PLAYER_MAXS: [f32; 3] = [16.0, 16.0, 36.0];
+
+// VEC_HUMAN_HULL_MIN and MAX, used by most monsters. Monsters stand on their origin.
+const HUMAN_MINS: [f32; 3] = [-16.0, -16.0, 0.0];
+const HUMAN_MAXS: [f32; 3] = [16.0, 16.0, 72.0];
+
+// Monsters that aren't human sized, with the size their Spawn sets in the
+// Half-Life SDK
+const MONSTER_SIZES: [(&str, [f32; 3], [f32; 3]); 17] = [
+    ("monster_headcrab", [-12.0, -12.0, 0.0], [12.0, 12.0, 24.0]),
+    ("monster_babycrab", [-12.0, -12.0, 0.0], [12.0, 12.0, 12.0]),
+    ("monster_houndeye", [-16.0, -16.0, 0.0], [16.0, 16.0, 36.0]),
+    ("monster_snark", [-4.0, -4.0, 0.0], [4.0, 4.0, 8.0]),
+    ("monster_cockroach", [-1.0, -1.0, 0.0], [1.0, 1.0, 2.0]),
+    ("monster_rat", [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+    ("monster_leech", [-1.0, -1.0, 0.0], [1.0, 1.0, 2.0]),
+    (
+        "monster_alien_grunt",
+        [-32.0, -32.0, 0.0],
+        [32.0, 32.0, 64.0],
+    ),
+    (
+        "monster_bullchicken",
+        [-32.0, -32.0, 0.0],
+        [32.0, 32.0, 64.0],
+    ),
+    ("monster_gargantua", [-32.0, -32.0, 0.0], [32.0, 32.0, 64.0]),
+    ("monster_bigmomma", [-32.0, -32.0, 0.0], [32.0, 32.0, 64.0]),
+    (
+        "monster_alien_controller",
+        [-32.0, -32.0, 0.0],
+        [32.0, 32.0, 64.0],
+    ),
+    (
+        "monster_ichthyosaur",
+        [-32.0, -32.0, -32.0],
+        [32.0, 32.0, 32.0],
+    ),
+    ("monster_tentacle", [-32.0, -32.0, 0.0], [32.0, 32.0, 64.0]),
+    ("monster_barnacle", [-16.0, -16.0, -32.0], [16.0, 16.0, 0.0]),
+    ("monster_apache", [-32.0, -32.0, -64.0], [32.0, 32.0, 0.0]),
+    (
+        "monster_osprey",
+        [-400.0, -400.0, -100.0],
+        [400.0, 400.0, 32.0],
+    ),
+];
+
+// Monsters that fly, swim or hang from the ceiling, so they don't need a floor
+const UNGROUNDED_MONSTERS: [&str; 12] = [
+    "monster_alien_controller",
+    "monster_apache",
+    "monster_barnacle",
+    "monster_flyer",
+    "monster_flyer_flock",
+    "monster_ichthyosaur",
+    "monster_leech",
+    "monster_miniturret",
+    "monster_nihilanth",
+    "monster_osprey",
+    "monster_sentry",
+    "monster_turret",
+];
+
+// Monsters that live in water
+const SWIMMING_MONSTERS: [&str; 2] = ["monster_ichthyosaur", "monster_leech"];
+
+// How far DROP_TO_FLOOR looks for the floor. Items with nothing below them
+// within this distance are removed when they spawn, monsters are left floating.
+const MAX_DROP_DISTANCE: f32 = 256.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspSpawnProblem {
+    /// The entity's box overlaps solid, so it can't move.
+    Stuck,
+    /// There's no floor within 256 units below the entity.
+    Floating,
+    /// The center of the entity's box is in water, slime or lava.
+    InLiquid,
+}
+
+impl BspSpawnProblem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BspSpawnProblem::Stuck => "stuck",
+            BspSpawnProblem::Floating => "floating",
+            BspSpawnProblem::InLiquid => "in liquid",
+        }
+    }
+}
+
+/// The placement of one spawn point or monster.
+#[derive(Clone, Debug)]
+pub struct BspSpawnCheck {
+    pub entity_index: usize,
+    pub classname: String,
+    pub origin: [f32; 3],
+    /// The hull the engine picks for the entity's size.
+    pub hull: BspHull,
+    /// Contents of that hull at the entity's position, solid if the entity is
+    /// stuck.
+    pub hull_contents: Option<BspContents>,
+    /// Contents of the render hull at the center of the entity's box, which is
+    /// where water, slime and lava show up.
+    pub contents: Option<BspContents>,
+    /// How far the entity would drop to reach the floor, `None` if there's no
+    /// floor within 256 units or the entity is stuck.
+    pub floor_distance: Option<f32>,
+    pub problems: Vec<BspSpawnProblem>,
+}
+
+/// Checks where player spawns and monsters are placed against the world's
+/// hulls. Player spawns use the standing hull, monsters the hull the engine
+/// picks for their size. Landmarks are checked with the standing hull too, but
+/// only for being stuck since they often float in the middle of a room. Brush
+/// entities are ignored.
+#[derive(Clone, Debug)]
+pub struct BspSpawnReport {
+    pub spawns: Vec<BspSpawnCheck>,
+}
+
+impl BspSpawnReport {
+    pub fn new(reader: &BspReader, entities: &[BspEntity]) -> Self {
+        let mut spawns = Vec::new();
+        for (entity_index, entity) in entities.iter().enumerate() {
+            let Some(classname) = entity.classname() else {
+                continue;
+            };
+            let is_landmark = classname == LANDMARK_CLASSNAME;
+            let (mins, maxs) = if PLAYER_CLASSNAMES.contains(&classname) || is_landmark {
+                (PLAYER_MINS, PLAYER_MAXS)
+            } else if classname.starts_with("monster_") {
+                MONSTER_SIZES
+                    .iter()
+                    .find(|(name, _, _)| *name == classname)
+                    .map(|(_, mins, maxs)| (*mins, *maxs))
+                    .unwrap_or((HUMAN_MINS, HUMAN_MAXS))
+            } else {
+                continue;
+            };
+            let origin = entity.get_vector("origin").unwrap_or([0.0; 3]);
+            let mut spawn = check_spawn(reader, entity_index, classname, origin, mins, maxs);
+            if is_landmark {
+                spawn.problems.retain(|x| *x == BspSpawnProblem::Stuck);
+            }
+            spawns.push(spawn);
+        }
+        Self { spawns }
+    }
+
+    pub fn from_reader(reader: &BspReader) -> Result<Self, BspEntityParseError> {
+        let entity_string = resolve_map_entity_string(reader);
+        let entities = BspEntity::parse_entities(&entity_string)?;
+        Ok(Self::new(reader, &entities))
+    }
+
+    /// Returns the spawns with at least one problem.
+    pub fn problems(&self) -> impl Iterator<Item = &BspSpawnCheck> {
+        self.spawns.iter().filter(|x| !x.problems.is_empty())
+    }
+}
+
+impl std::fmt::Display for BspSpawnReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for spawn in self.problems() {
+            let problems: Vec<_> = spawn.problems.iter().map(|x| x.name()).collect();
+            let [x, y, z] = spawn.origin;
+            writeln!(
+                f,
+                "{} {} at ({} {} {}): {} (hull {:?}, contents {:?})",
+                spawn.entity_index,
+                spawn.classname,
+                x,
+                y,
+                z,
+                problems.join(", "),
+                spawn.hull_contents,
+                spawn.contents
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Picks a hull from an entity's size the way the engine's SV_HullForBsp does
+fn get_hull_for_size(mins: [f32; 3], maxs: [f32; 3]) -> BspHull {
+    let size = [0, 1, 2].map(|i| maxs[i] - mins[i]);
+    if size[0] <= 8.0 {
+        BspHull::Point
+    } else if size[0] <= 36.0 {
+        if size[2] <= 36.0 {
+            BspHull::Crouching
+        } else {
+            BspHull::Standing
+        }
+    } else {
+        BspHull::Large
+    }
+}
+
+fn check_spawn(
+    reader: &BspReader,
+    entity_index: usize,
+    classname: &str,
+    origin: [f32; 3],
+    mins: [f32; 3],
+    maxs: [f32; 3],
+) -> BspSpawnCheck {
+    let hull = get_hull_for_size(mins, maxs);
+    // Hulls are centered on their own mins and maxs, so the entity's box is
+    // shifted to line up with the hull
+    let hull_mins = hull.mins();
+    let position = [0, 1, 2].map(|i| origin[i] + mins[i] - hull_mins[i]);
+    let center = [0, 1, 2].map(|i| origin[i] + (mins[i] + maxs[i]) * 0.5);
+
+    let hull_contents = reader.get_hull_point_contents(0, hull, position);
+    let contents = reader.get_hull_point_contents(0, BspHull::Point, center);
+    let stuck = hull_contents == Some(BspContents::Solid);
+    let end = [position[0], position[1], position[2] - MAX_DROP_DISTANCE];
+    let floor_distance = reader
+        .trace_hull(0, hull, position, end)
+        .filter(|trace| !stuck && !trace.start_solid && trace.fraction < 1.0)
+        .map(|trace| trace.fraction * MAX_DROP_DISTANCE);
+
+    let mut problems = Vec::new();
+    if stuck {
+        problems.push(BspSpawnProblem::Stuck);
+    }
+    if !stuck && floor_distance.is_none() && !UNGROUNDED_MONSTERS.contains(&classname) {
+        problems.push(BspSpawnProblem::Floating);
+    }
+    let in_liquid = match contents {
+        Some(BspContents::Water) => !SWIMMING_MONSTERS.contains(&classname),
+        Some(BspContents::Slime | BspContents::Lava) => true,
+        _ => false,
+    };
+    if in_liquid {
+        problems.push(BspSpawnProblem::InLiquid);
+    }
+
+    BspSpawnCheck {
+        entity_index,
+        classname: classname.to_owned(),
+        origin,
+        hull,
+        hull_contents,
+        contents,
+        floor_distance,
+        problems,
+    }
+}
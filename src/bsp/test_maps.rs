@@ -0,0 +1,86 @@
+// Small maps built in code for tests
+
+use super::{
+    BspEdge, BspFace, BspFile, BspLeaf, BspModel, BspNode, BspPlane, BspReader, BspSurfaceEdge,
+    BspTextureInfo, BspVertex,
+};
+
+/// A world made of one node on the z = 0 plane, with empty space above and solid
+/// below. The node holds a single lit face, a square with corners at
+/// `±half_size` facing up.
+pub(crate) fn single_face_map(half_size: f32) -> BspFile {
+    let mut file = BspFile::new();
+    file.set_entities_str("{\n\"classname\" \"worldspawn\"\n}\n");
+    file.planes.push(BspPlane {
+        normal: [0.0, 0.0, 1.0],
+        dist: 0.0,
+        ty: 2,
+    });
+    for [x, y] in [[-1.0, -1.0], [-1.0, 1.0], [1.0, 1.0], [1.0, -1.0]] {
+        file.vertices.push(BspVertex {
+            x: x * half_size,
+            y: y * half_size,
+            z: 0.0,
+        });
+    }
+    // Edge 0 is never used by faces
+    file.edges.push(BspEdge { vertices: [0, 0] });
+    for i in 0..4u16 {
+        file.edges.push(BspEdge {
+            vertices: [i, (i + 1) % 4],
+        });
+        file.surface_edges.push(BspSurfaceEdge(i as i32 + 1));
+    }
+    file.texture_infos.push(BspTextureInfo {
+        s: [1.0, 0.0, 0.0],
+        s_shift: 0.0,
+        t: [0.0, 1.0, 0.0],
+        t_shift: 0.0,
+        texture_index: 0,
+        flags: 0,
+    });
+    file.faces.push(BspFace {
+        plane: 0,
+        plane_side: 0,
+        first_edge: 0,
+        edges: 4,
+        texture_info: 0,
+        styles: [0, 255, 255, 255],
+        lightmap_offset: 0,
+    });
+    file.lighting = vec![128; 256];
+    file.nodes.push(BspNode {
+        plane: 0,
+        children: [-2, -1],
+        mins: [-4096; 3],
+        maxs: [4096; 3],
+        first_face: 0,
+        faces: 1,
+    });
+    for contents in [-2, -1] {
+        file.leaves.push(BspLeaf {
+            contents,
+            vis_offset: -1,
+            mins: [-4096; 3],
+            maxs: [4096; 3],
+            first_mark_surface: 0,
+            mark_surfaces: 0,
+            ambient_levels: [0; 4],
+        });
+    }
+    file.models.push(BspModel {
+        mins: [-half_size, -half_size, -1.0],
+        maxs: [half_size, half_size, 1.0],
+        origin: [0.0; 3],
+        head_nodes: [0, -1, -1, -1],
+        vis_leaves: 1,
+        first_face: 0,
+        faces: 1,
+    });
+    file
+}
+
+/// Reads a map built by one of the functions above.
+pub(crate) fn read(file: &BspFile) -> BspReader<'static> {
+    BspReader::read(file.to_bytes()).unwrap()
+}
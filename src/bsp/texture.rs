@@ -0,0 +1,171 @@
+use super::{BspMipTextureReader, BspReader, BspTextureInfo};
+
+/// Texinfo flag set on surfaces that aren't lightmapped or subdivided (sky, liquids
+/// and tool textures).
+pub const TEX_SPECIAL: u32 = 1;
+
+/// What the engine and compilers do with a texture, based on its name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspTextureKind {
+    Normal,
+    /// `+0`..`+9` (primary set) or `+a`..`+j` (alternate set, used when the
+    /// entity's frame is 1).
+    Animated {
+        frame: u8,
+        alternate: bool,
+    },
+    /// `-0`..`-9`, tiles of the same base name are picked randomly per surface.
+    RandomTiled {
+        tile: u8,
+    },
+    /// `!` (or Quake's `*`), water, slime and lava with warped surfaces.
+    Liquid,
+    /// Names starting with `sky`, drawn with the skybox.
+    Sky,
+    /// `{`, palette index 255 is transparent.
+    Masked,
+    /// `aaatrigger`, removed from the rendered geometry of trigger brushes.
+    Trigger,
+    /// `clip`, only present in the clip hulls.
+    Clip,
+    /// `origin`, sets the rotation origin of its brush entity.
+    Origin,
+}
+
+impl BspTextureKind {
+    pub fn from_name(name: &str) -> Self {
+        let bytes = name.as_bytes();
+        match bytes {
+            [b'+', frame, ..] => match frame.to_ascii_lowercase() {
+                x @ b'0'..=b'9' => {
+                    return BspTextureKind::Animated {
+                        frame: x - b'0',
+                        alternate: false,
+                    };
+                }
+                x @ b'a'..=b'j' => {
+                    return BspTextureKind::Animated {
+                        frame: x - b'a',
+                        alternate: true,
+                    };
+                }
+                _ => {}
+            },
+            [b'-', x @ b'0'..=b'9', ..] => return BspTextureKind::RandomTiled { tile: x - b'0' },
+            [b'!' | b'*', ..] => return BspTextureKind::Liquid,
+            [b'{', ..] => return BspTextureKind::Masked,
+            _ => {}
+        }
+
+        if bytes.len() >= 3 && bytes[..3].eq_ignore_ascii_case(b"sky") {
+            BspTextureKind::Sky
+        } else if name.eq_ignore_ascii_case("aaatrigger") {
+            BspTextureKind::Trigger
+        } else if name.eq_ignore_ascii_case("clip") {
+            BspTextureKind::Clip
+        } else if name.eq_ignore_ascii_case("origin") {
+            BspTextureKind::Origin
+        } else {
+            BspTextureKind::Normal
+        }
+    }
+
+    /// Returns true for textures that only exist to guide the compiler and never
+    /// render in game.
+    pub fn is_tool(&self) -> bool {
+        matches!(
+            self,
+            BspTextureKind::Trigger | BspTextureKind::Clip | BspTextureKind::Origin
+        )
+    }
+}
+
+/// Returns the name shared by all frames or tiles of an animated or random tiled
+/// texture, e.g. `lab` for `+0lab`. Other names are returned unchanged.
+pub fn get_texture_base_name(name: &str) -> &str {
+    match BspTextureKind::from_name(name) {
+        BspTextureKind::Animated { .. } | BspTextureKind::RandomTiled { .. } => &name[2..],
+        _ => name,
+    }
+}
+
+/// The frames of one animated texture, as texture indices.
+#[derive(Clone, Debug)]
+pub struct BspTextureAnimation {
+    pub base_name: String,
+    /// Frames `+0`..`+9`, in frame order.
+    pub frames: Vec<usize>,
+    /// Frames `+a`..`+j`, in frame order.
+    pub alternate_frames: Vec<usize>,
+}
+
+impl BspTextureInfo {
+    pub fn is_special(&self) -> bool {
+        self.flags & TEX_SPECIAL != 0
+    }
+}
+
+impl BspMipTextureReader<'_> {
+    pub fn kind(&self) -> BspTextureKind {
+        BspTextureKind::from_name(self.get_image_name())
+    }
+}
+
+impl BspReader<'_> {
+    pub fn get_texture_kinds(&self) -> Vec<Option<BspTextureKind>> {
+        let textures = self.read_textures();
+        (0..textures.len())
+            .map(|i| textures.get(i).map(|x| x.kind()))
+            .collect()
+    }
+
+    /// Groups animated textures by base name (case-insensitive). Frames are
+    /// ordered by their frame number; the engine refuses to load maps with gaps,
+    /// so a gap in the sequence isn't represented.
+    pub fn get_texture_animations(&self) -> Vec<BspTextureAnimation> {
+        let textures = self.read_textures();
+        let mut animations: Vec<BspTextureAnimation> = Vec::new();
+        let mut frame_numbers: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        for texture_index in 0..textures.len() {
+            let Some(texture) = textures.get(texture_index) else {
+                continue;
+            };
+            let name = texture.get_image_name();
+            let BspTextureKind::Animated { frame, alternate } = BspTextureKind::from_name(name)
+            else {
+                continue;
+            };
+            let base_name = get_texture_base_name(name);
+
+            let animation_index = match animations
+                .iter()
+                .position(|x| x.base_name.eq_ignore_ascii_case(base_name))
+            {
+                Some(index) => index,
+                None => {
+                    animations.push(BspTextureAnimation {
+                        base_name: base_name.to_owned(),
+                        frames: Vec::new(),
+                        alternate_frames: Vec::new(),
+                    });
+                    frame_numbers.push((Vec::new(), Vec::new()));
+                    animations.len() - 1
+                }
+            };
+
+            let animation = &mut animations[animation_index];
+            let (frames, numbers) = if alternate {
+                (
+                    &mut animation.alternate_frames,
+                    &mut frame_numbers[animation_index].1,
+                )
+            } else {
+                (&mut animation.frames, &mut frame_numbers[animation_index].0)
+            };
+            let position = numbers.partition_point(|x| *x <= frame);
+            numbers.insert(position, frame);
+            frames.insert(position, texture_index);
+        }
+        animations
+    }
+}
@@ -0,0 +1,391 @@
+use super::{BspContents, BspNodeChild, BspPlane, BspReader, FromValue};
+
+// Keeps the impact point this far on the near side of the plane
+const DIST_EPSILON: f32 = 0.03125;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspHull {
+    Point = 0,
+    Standing = 1,
+    Large = 2,
+    Crouching = 3,
+}
+
+impl BspHull {
+    pub const ALL: [BspHull; 4] = [
+        BspHull::Point,
+        BspHull::Standing,
+        BspHull::Large,
+        BspHull::Crouching,
+    ];
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn mins(&self) -> [f32; 3] {
+        match self {
+            BspHull::Point => [0.0, 0.0, 0.0],
+            BspHull::Standing => [-16.0, -16.0, -36.0],
+            BspHull::Large => [-32.0, -32.0, -32.0],
+            BspHull::Crouching => [-16.0, -16.0, -18.0],
+        }
+    }
+
+    pub fn maxs(&self) -> [f32; 3] {
+        match self {
+            BspHull::Point => [0.0, 0.0, 0.0],
+            BspHull::Standing => [16.0, 16.0, 36.0],
+            BspHull::Large => [32.0, 32.0, 32.0],
+            BspHull::Crouching => [16.0, 16.0, 18.0],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BspTracePlane {
+    pub normal: [f32; 3],
+    pub dist: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BspTrace {
+    /// The trace never left solid space.
+    pub all_solid: bool,
+    /// The trace started in solid space.
+    pub start_solid: bool,
+    /// The trace passed through empty space.
+    pub in_open: bool,
+    /// The trace passed through non-solid, non-empty space such as water.
+    pub in_water: bool,
+    /// How far along the line the trace got before hitting something, 1.0 if it didn't.
+    pub fraction: f32,
+    pub end_position: [f32; 3],
+    /// The plane that was hit, facing back towards the start.
+    pub plane: Option<BspTracePlane>,
+    /// The contents at the end position.
+    pub contents: Option<BspContents>,
+}
+
+/// A view over one of a model's collision hulls. Hull 0 is built from the
+/// render nodes, the others from clip nodes.
+pub(crate) struct BspHullTree<'a> {
+    reader: &'a BspReader<'a>,
+    hull: BspHull,
+    head_node: i32,
+}
+
+impl<'a> BspHullTree<'a> {
+    pub(crate) fn new(
+        reader: &'a BspReader<'a>,
+        model_index: usize,
+        hull: BspHull,
+    ) -> Option<Self> {
+        let model = reader.read_models().get(model_index)?;
+        Some(Self {
+            reader,
+            hull,
+            head_node: model.head_nodes[hull.index()],
+        })
+    }
+
+    /// Node indices are positive, contents are negative.
+    pub(crate) fn head_node(&self) -> i32 {
+        self.head_node
+    }
+
+    pub(crate) fn max_depth(&self) -> usize {
+        match self.hull {
+            BspHull::Point => self.reader.read_nodes().len(),
+            _ => self.reader.read_clip_nodes().len(),
+        }
+    }
+
+    pub(crate) fn plane(&self, node: i32) -> Option<&'a BspPlane> {
+        let plane_index = match self.hull {
+            BspHull::Point => self.reader.read_nodes().get(node as usize)?.plane as usize,
+            _ => {
+                self.reader
+                    .read_clip_nodes()
+                    .get(node as usize)?
+                    .plane_index as usize
+            }
+        };
+        self.reader.read_planes().get(plane_index)
+    }
+
+    /// Returns the child node, or contents if the child is a leaf.
+    pub(crate) fn child(&self, node: i32, side: usize) -> Option<i32> {
+        match self.hull {
+            BspHull::Point => {
+                let node = self.reader.read_nodes().get(node as usize)?;
+                match node.child(side) {
+                    BspNodeChild::Node(index) => Some(index as i32),
+                    BspNodeChild::Leaf(index) => {
+                        Some(self.reader.read_leaves().get(index)?.contents)
+                    }
+                }
+            }
+            _ => {
+                let node = self.reader.read_clip_nodes().get(node as usize)?;
+                Some(node.children[side] as i32)
+            }
+        }
+    }
+
+    /// Returns the raw contents value at a point, starting from `node`.
+    pub(crate) fn point_contents(&self, mut node: i32, point: [f32; 3]) -> i32 {
+        let mut depth = 0;
+        while node >= 0 {
+            depth += 1;
+            let (Some(plane), true) = (self.plane(node), depth <= self.max_depth()) else {
+                return BspContents::Solid as i32;
+            };
+            let side = if plane.distance_to(point) < 0.0 { 1 } else { 0 };
+            let Some(child) = self.child(node, side) else {
+                return BspContents::Solid as i32;
+            };
+            node = child;
+        }
+        node
+    }
+}
+
+impl BspReader<'_> {
+    /// Returns the contents of a point in one of a model's hulls, in model space.
+    pub fn get_hull_point_contents(
+        &self,
+        model_index: usize,
+        hull: BspHull,
+        point: [f32; 3],
+    ) -> Option<BspContents> {
+        let tree = BspHullTree::new(self, model_index, hull)?;
+        BspContents::from_value(tree.point_contents(tree.head_node(), point))
+    }
+
+    /// Sweeps a hull sized box from `start` to `end` through a model, like the
+    /// engine's `SV_RecursiveHullCheck`. Positions are the center of the box in
+    /// model space.
+    pub fn trace_hull(
+        &self,
+        model_index: usize,
+        hull: BspHull,
+        start: [f32; 3],
+        end: [f32; 3],
+    ) -> Option<BspTrace> {
+        let tree = BspHullTree::new(self, model_index, hull)?;
+        let mut trace = BspTrace {
+            all_solid: true,
+            start_solid: false,
+            in_open: false,
+            in_water: false,
+            fraction: 1.0,
+            end_position: end,
+            plane: None,
+            contents: None,
+        };
+        recursive_hull_check(&tree, tree.head_node(), 0.0, 1.0, start, end, &mut trace, 0);
+        if trace.all_solid {
+            trace.start_solid = true;
+        }
+        if trace.start_solid {
+            trace.fraction = 0.0;
+        }
+        trace.contents =
+            BspContents::from_value(tree.point_contents(tree.head_node(), trace.end_position));
+        Some(trace)
+    }
+}
+
+fn lerp(start: [f32; 3], end: [f32; 3], frac: f32) -> [f32; 3] {
+    [
+        start[0] + frac * (end[0] - start[0]),
+        start[1] + frac * (end[1] - start[1]),
+        start[2] + frac * (end[2] - start[2]),
+    ]
+}
+
+// Returns false once the trace has hit something
+#[allow(clippy::too_many_arguments)]
+fn recursive_hull_check(
+    tree: &BspHullTree,
+    node: i32,
+    p1f: f32,
+    p2f: f32,
+    p1: [f32; 3],
+    p2: [f32; 3],
+    trace: &mut BspTrace,
+    depth: usize,
+) -> bool {
+    if node < 0 {
+        if node != BspContents::Solid as i32 {
+            trace.all_solid = false;
+            if node == BspContents::Empty as i32 {
+                trace.in_open = true;
+            } else {
+                trace.in_water = true;
+            }
+        } else {
+            trace.start_solid = true;
+        }
+        return true;
+    }
+
+    let (Some(plane), true) = (tree.plane(node), depth <= tree.max_depth()) else {
+        return true;
+    };
+    let (Some(front), Some(back)) = (tree.child(node, 0), tree.child(node, 1)) else {
+        return true;
+    };
+
+    let t1 = plane.distance_to(p1);
+    let t2 = plane.distance_to(p2);
+    if t1 >= 0.0 && t2 >= 0.0 {
+        return recursive_hull_check(tree, front, p1f, p2f, p1, p2, trace, depth + 1);
+    }
+    if t1 < 0.0 && t2 < 0.0 {
+        return recursive_hull_check(tree, back, p1f, p2f, p1, p2, trace, depth + 1);
+    }
+
+    // Put the crosspoint DIST_EPSILON units on the near side
+    let mut frac = if t1 < 0.0 {
+        (t1 + DIST_EPSILON) / (t1 - t2)
+    } else {
+        (t1 - DIST_EPSILON) / (t1 - t2)
+    };
+    frac = frac.clamp(0.0, 1.0);
+    let mut midf = p1f + (p2f - p1f) * frac;
+    let mut mid = lerp(p1, p2, frac);
+
+    let side = if t1 < 0.0 { 1 } else { 0 };
+    let (near, far) = if side == 0 {
+        (front, back)
+    } else {
+        (back, front)
+    };
+
+    // Move up to the node
+    if !recursive_hull_check(tree, near, p1f, midf, p1, mid, trace, depth + 1) {
+        return false;
+    }
+
+    // Go past the node
+    if tree.point_contents(far, mid) != BspContents::Solid as i32 {
+        return recursive_hull_check(tree, far, midf, p2f, mid, p2, trace, depth + 1);
+    }
+
+    // Never got out of the solid area
+    if trace.all_solid {
+        return false;
+    }
+
+    // The other side of the node is solid, this is the impact point
+    trace.plane = Some(if side == 0 {
+        BspTracePlane {
+            normal: plane.normal,
+            dist: plane.dist,
+        }
+    } else {
+        BspTracePlane {
+            normal: plane.normal.map(|x| -x),
+            dist: -plane.dist,
+        }
+    });
+
+    // The engine backs off when the impact point is still in solid. This
+    // shouldn't happen, but does occasionally.
+    while tree.point_contents(tree.head_node(), mid) == BspContents::Solid as i32 {
+        frac -= 0.1;
+        if frac < 0.0 {
+            trace.fraction = midf;
+            trace.end_position = mid;
+            return false;
+        }
+        midf = p1f + (p2f - p1f) * frac;
+        mid = lerp(p1, p2, frac);
+    }
+
+    trace.fraction = midf;
+    trace.end_position = mid;
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+    use super::*;
+
+    // The test map's hull is a single plane at z = 0, empty above and solid below
+    fn trace(start: [f32; 3], end: [f32; 3]) -> BspTrace {
+        let file = test_maps::single_face_map(8.0);
+        let reader = test_maps::read(&file);
+        reader.trace_hull(0, BspHull::Point, start, end).unwrap()
+    }
+
+    #[test]
+    fn point_contents() {
+        let file = test_maps::single_face_map(8.0);
+        let reader = test_maps::read(&file);
+        let contents = |z| reader.get_hull_point_contents(0, BspHull::Point, [0.0, 0.0, z]);
+        assert_eq!(contents(1.0), Some(BspContents::Empty));
+        assert_eq!(contents(0.0), Some(BspContents::Empty));
+        assert_eq!(contents(-1.0), Some(BspContents::Solid));
+    }
+
+    #[test]
+    fn trace_into_plane() {
+        let trace = trace([0.0, 0.0, 10.0], [0.0, 0.0, -10.0]);
+        assert!(!trace.start_solid);
+        assert!(!trace.all_solid);
+        assert!(trace.in_open);
+        // Stops DIST_EPSILON short of the plane
+        assert_eq!(trace.fraction, (10.0 - DIST_EPSILON) / 20.0);
+        assert!((trace.end_position[2] - DIST_EPSILON).abs() < 1e-5);
+        assert_eq!(
+            trace.plane,
+            Some(BspTracePlane {
+                normal: [0.0, 0.0, 1.0],
+                dist: 0.0,
+            })
+        );
+        assert_eq!(trace.contents, Some(BspContents::Empty));
+    }
+
+    #[test]
+    fn trace_along_plane() {
+        // On the plane counts as in front of it
+        for z in [1.0, 0.0] {
+            let end = [5.0, 0.0, z];
+            let trace = trace([-5.0, 0.0, z], end);
+            assert!(!trace.start_solid);
+            assert!(!trace.all_solid);
+            assert_eq!(trace.fraction, 1.0);
+            assert_eq!(trace.end_position, end);
+            assert_eq!(trace.plane, None);
+        }
+    }
+
+    #[test]
+    fn trace_out_of_solid() {
+        let trace = trace([0.0, 0.0, -10.0], [0.0, 0.0, 10.0]);
+        assert!(trace.start_solid);
+        assert!(!trace.all_solid);
+        assert!(trace.in_open);
+        assert_eq!(trace.fraction, 0.0);
+        assert_eq!(trace.plane, None);
+    }
+
+    #[test]
+    fn trace_inside_solid() {
+        let trace = trace([-5.0, 0.0, -1.0], [5.0, 0.0, -1.0]);
+        assert!(trace.start_solid);
+        assert!(trace.all_solid);
+        assert!(!trace.in_open);
+        assert_eq!(trace.fraction, 0.0);
+        assert_eq!(trace.contents, Some(BspContents::Solid));
+    }
+}
@@ -0,0 +1,179 @@
+use super::{BspContents, BspNodeChild, BspReader};
+
+impl BspReader<'_> {
+    /// Returns the leaf containing a point in the render hull (hull 0) of a model.
+    /// The point is in the model's local space.
+    pub fn find_leaf(&self, model_index: usize, point: [f32; 3]) -> Option<usize> {
+        let model = self.read_models().get(model_index)?;
+        let nodes = self.read_nodes();
+        let planes = self.read_planes();
+
+        let head_node = model.head_nodes[0];
+        if head_node < 0 {
+            return None;
+        }
+        let mut child = BspNodeChild::Node(head_node as usize);
+        // A well formed tree can't be deeper than it has nodes
+        for _ in 0..=nodes.len() {
+            match child {
+                BspNodeChild::Node(node_index) => {
+                    let node = nodes.get(node_index)?;
+                    let plane = planes.get(node.plane as usize)?;
+                    let side = if plane.distance_to(point) >= 0.0 {
+                        0
+                    } else {
+                        1
+                    };
+                    child = node.child(side);
+                }
+                BspNodeChild::Leaf(leaf_index) => {
+                    return if leaf_index < self.read_leaves().len() {
+                        Some(leaf_index)
+                    } else {
+                        None
+                    };
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the contents of the leaf containing a point in the render hull of a model.
+    pub fn get_point_contents(&self, model_index: usize, point: [f32; 3]) -> Option<BspContents> {
+        let leaf_index = self.find_leaf(model_index, point)?;
+        let leaf = self.read_leaves().get(leaf_index)?;
+        leaf.try_contents()
+    }
+
+    /// Walks the render hull of a model front to back as seen from `viewpoint`.
+    /// Nodes are visited after everything in front of them and before everything
+    /// behind them, which is the order the engine draws surfaces in.
+    pub fn walk_front_to_back<F: FnMut(BspNodeChild)>(
+        &self,
+        model_index: usize,
+        viewpoint: [f32; 3],
+        mut visitor: F,
+    ) {
+        let Some(model) = self.read_models().get(model_index) else {
+            return;
+        };
+        if model.head_nodes[0] < 0 {
+            return;
+        }
+        let nodes = self.read_nodes();
+        let planes = self.read_planes();
+
+        enum Step {
+            Enter(BspNodeChild),
+            Visit(usize),
+        }
+
+        // Every node in a tree is entered and visited once, and has one child
+        // more than there are nodes. Bound the walk so a corrupt tree with
+        // cycles can't run forever.
+        let mut budget = nodes.len() * 3 + 1;
+        let mut stack = vec![Step::Enter(BspNodeChild::Node(
+            model.head_nodes[0] as usize,
+        ))];
+        while let Some(step) = stack.pop() {
+            if budget == 0 {
+                break;
+            }
+            budget -= 1;
+
+            match step {
+                Step::Enter(BspNodeChild::Leaf(leaf_index)) => {
+                    visitor(BspNodeChild::Leaf(leaf_index));
+                }
+                Step::Enter(BspNodeChild::Node(node_index)) => {
+                    let Some(node) = nodes.get(node_index) else {
+                        continue;
+                    };
+                    let Some(plane) = planes.get(node.plane as usize) else {
+                        continue;
+                    };
+                    let front = if plane.distance_to(viewpoint) >= 0.0 {
+                        0
+                    } else {
+                        1
+                    };
+                    // Pushed in reverse, the front side is walked first
+                    stack.push(Step::Enter(node.child(front ^ 1)));
+                    stack.push(Step::Visit(node_index));
+                    stack.push(Step::Enter(node.child(front)));
+                }
+                Step::Visit(node_index) => {
+                    visitor(BspNodeChild::Node(node_index));
+                }
+            }
+        }
+    }
+
+    /// Returns every leaf of a model's render hull that a box touches. The box is
+    /// in the model's local space.
+    pub fn find_leaves_in_box(
+        &self,
+        model_index: usize,
+        mins: [f32; 3],
+        maxs: [f32; 3],
+    ) -> Vec<usize> {
+        let mut leaves = Vec::new();
+        let Some(model) = self.read_models().get(model_index) else {
+            return leaves;
+        };
+        if model.head_nodes[0] < 0 {
+            return leaves;
+        }
+        let nodes = self.read_nodes();
+        let planes = self.read_planes();
+
+        // Bound the walk the same way walk_front_to_back does
+        let mut budget = nodes.len() * 2 + 1;
+        let mut seen = vec![false; self.read_leaves().len()];
+        let mut stack = vec![BspNodeChild::Node(model.head_nodes[0] as usize)];
+        while let Some(child) = stack.pop() {
+            if budget == 0 {
+                break;
+            }
+            budget -= 1;
+
+            match child {
+                BspNodeChild::Leaf(leaf_index) => {
+                    // Solid leaves are shared, so a leaf can be reached more than once
+                    if seen.get(leaf_index) == Some(&false) {
+                        seen[leaf_index] = true;
+                        leaves.push(leaf_index);
+                    }
+                }
+                BspNodeChild::Node(node_index) => {
+                    let Some(node) = nodes.get(node_index) else {
+                        continue;
+                    };
+                    let Some(plane) = planes.get(node.plane as usize) else {
+                        continue;
+                    };
+                    // The box corner furthest along the normal decides if any of
+                    // the box is in front, the nearest one if any is behind
+                    let mut near = [0.0; 3];
+                    let mut far = [0.0; 3];
+                    for i in 0..3 {
+                        if plane.normal[i] >= 0.0 {
+                            near[i] = mins[i];
+                            far[i] = maxs[i];
+                        } else {
+                            near[i] = maxs[i];
+                            far[i] = mins[i];
+                        }
+                    }
+                    if plane.distance_to(far) >= 0.0 {
+                        stack.push(node.child(0));
+                    }
+                    if plane.distance_to(near) < 0.0 {
+                        stack.push(node.child(1));
+                    }
+                }
+            }
+        }
+        leaves
+    }
+}
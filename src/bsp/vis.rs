@@ -0,0 +1,164 @@
+use super::BspReader;
+
+/// The decompressed potentially visible set of every leaf in the world.
+///
+/// Bit `i` of a row is leaf `i + 1`, since leaf 0 is the shared solid leaf and
+/// is never part of the PVS. Only the world model's `vis_leaves` leaves are
+/// covered; brush model leaves don't have visibility data.
+#[derive(Clone, Debug)]
+pub struct BspPvs {
+    vis_leaves: usize,
+    rows: Vec<Vec<u8>>,
+}
+
+impl BspPvs {
+    /// The number of leaves covered by the PVS, not counting leaf 0.
+    pub fn vis_leaves(&self) -> usize {
+        self.vis_leaves
+    }
+
+    /// Returns the decompressed row for a leaf.
+    pub fn row(&self, leaf_index: usize) -> Option<&[u8]> {
+        self.rows.get(leaf_index).map(|x| x.as_slice())
+    }
+
+    /// Returns true if `to_leaf` is potentially visible from `from_leaf`.
+    pub fn is_visible(&self, from_leaf: usize, to_leaf: usize) -> bool {
+        if to_leaf == 0 || to_leaf > self.vis_leaves {
+            return false;
+        }
+        match self.row(from_leaf) {
+            Some(row) => is_bit_set(row, to_leaf - 1),
+            None => false,
+        }
+    }
+
+    /// Returns every leaf potentially visible from `from_leaf`.
+    pub fn get_visible_leaves(&self, from_leaf: usize) -> Vec<usize> {
+        let Some(row) = self.row(from_leaf) else {
+            return Vec::new();
+        };
+        (0..self.vis_leaves)
+            .filter(|bit| is_bit_set(row, *bit))
+            .map(|bit| bit + 1)
+            .collect()
+    }
+}
+
+impl BspReader<'_> {
+    /// Decompresses the PVS row for a single leaf. Like the engine, leaf 0 and
+    /// leaves without visibility data see everything. Returns `None` if the leaf
+    /// doesn't exist, isn't covered by the world's `vis_leaves`, or its data is
+    /// truncated.
+    pub fn decompress_leaf_pvs(&self, leaf_index: usize) -> Option<Vec<u8>> {
+        let vis_leaves = self.get_world_vis_leaves();
+        if leaf_index > vis_leaves {
+            return None;
+        }
+        let leaf = self.read_leaves().get(leaf_index)?;
+        let row_len = vis_leaves.div_ceil(8);
+
+        let visibility = self.read_visibility_data();
+        if leaf_index == 0 || leaf.vis_offset < 0 || visibility.is_empty() {
+            return Some(all_visible_row(vis_leaves));
+        }
+
+        let mut input = visibility.get(leaf.vis_offset as usize..)?.iter();
+        let mut row = Vec::with_capacity(row_len);
+        while row.len() < row_len {
+            let value = *input.next()?;
+            if value != 0 {
+                row.push(value);
+            } else {
+                // A zero is followed by the number of zero bytes to write
+                let count = *input.next()? as usize;
+                let count = count.min(row_len - row.len());
+                row.resize(row.len() + count, 0);
+            }
+        }
+        Some(row)
+    }
+
+    /// Decompresses the PVS for every leaf in the world. Leaves with truncated
+    /// visibility data are treated as seeing everything.
+    pub fn read_pvs(&self) -> BspPvs {
+        let vis_leaves = self.get_world_vis_leaves();
+        let rows = (0..=vis_leaves)
+            .map(|leaf_index| {
+                self.decompress_leaf_pvs(leaf_index)
+                    .unwrap_or_else(|| all_visible_row(vis_leaves))
+            })
+            .collect();
+        BspPvs { vis_leaves, rows }
+    }
+
+    fn get_world_vis_leaves(&self) -> usize {
+        let vis_leaves = self
+            .read_models()
+            .first()
+            .map(|x| x.vis_leaves.max(0) as usize)
+            .unwrap_or(0);
+        // Never claim more leaves than the map has, leaf 0 isn't counted
+        vis_leaves.min(self.read_leaves().len().saturating_sub(1))
+    }
+}
+
+fn all_visible_row(vis_leaves: usize) -> Vec<u8> {
+    let mut row = vec![0xFF; vis_leaves.div_ceil(8)];
+    // Clear the padding bits so counting set bits gives the number of leaves
+    if let Some(last) = row.last_mut()
+        && !vis_leaves.is_multiple_of(8)
+    {
+        *last = (1u8 << (vis_leaves % 8)) - 1;
+    }
+    row
+}
+
+fn is_bit_set(row: &[u8], bit: usize) -> bool {
+    row.get(bit >> 3)
+        .map(|x| x & (1 << (bit & 7)) != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+    use super::*;
+
+    #[test]
+    fn decompress_runs() {
+        // 20 leaves make rows of 3 bytes
+        let mut file = test_maps::single_face_map(8.0);
+        let empty = file.leaves[1];
+        file.leaves.resize(21, empty);
+        file.models[0].vis_leaves = 20;
+        // Leaf 1's run of 5 zeros goes past the end of its row, leaf 2 starts
+        // with a run and leaf 3's data is cut off after a zero
+        file.visibility = vec![0x05, 0x00, 0x05, 0x00, 0x02, 0x01, 0x00];
+        file.leaves[1].vis_offset = 0;
+        file.leaves[2].vis_offset = 3;
+        file.leaves[3].vis_offset = 6;
+        file.leaves[4].vis_offset = 0;
+        file.leaves[5].vis_offset = -1;
+        let reader = test_maps::read(&file);
+
+        assert_eq!(reader.decompress_leaf_pvs(1).unwrap(), [0x05, 0x00, 0x00]);
+        assert_eq!(reader.decompress_leaf_pvs(2).unwrap(), [0x00, 0x00, 0x01]);
+        assert_eq!(reader.decompress_leaf_pvs(3), None);
+        assert_eq!(reader.decompress_leaf_pvs(21), None);
+
+        let pvs = reader.read_pvs();
+        assert_eq!(pvs.vis_leaves(), 20);
+        assert_eq!(pvs.get_visible_leaves(1), [1, 3]);
+        assert_eq!(pvs.get_visible_leaves(2), [17]);
+        assert_eq!(pvs.get_visible_leaves(4), [1, 3]);
+        // Truncated data, missing data and leaf 0 see everything
+        for leaf in [0, 3, 5] {
+            assert_eq!(pvs.row(leaf).unwrap(), [0xFF, 0xFF, 0x0F]);
+        }
+        assert!(pvs.is_visible(2, 17));
+        assert!(!pvs.is_visible(2, 16));
+        assert!(!pvs.is_visible(0, 0));
+        assert!(!pvs.is_visible(0, 21));
+    }
+}
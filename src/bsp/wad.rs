@@ -0,0 +1,422 @@
+use std::path::{Path, PathBuf};
+
+use crate::path::{PathPal, find_file_ignore_case};
+use crate::util::{
+    NullTerminatedStrError, null_terminated_bytes_to_str, resolve_map_entity_string,
+};
+use crate::wad3::{MipmapedTextureData, TextureType, WadArchive, WadWriter};
+
+use super::{
+    BspEntity, BspEntityParseError, BspEntitySerializeError, BspFile, BspMipTextureHeader,
+    BspMipTextureReader, BspReader,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BspTextureSource {
+    Embedded,
+    Wad(PathBuf),
+    Missing,
+}
+
+#[derive(Clone)]
+pub struct BspResolvedTexture {
+    pub name: String,
+    pub source: BspTextureSource,
+    pub data: Option<MipmapedTextureData>,
+}
+
+#[derive(Clone)]
+pub struct BspResolvedTextures {
+    /// Indexed by texture index.
+    pub textures: Vec<BspResolvedTexture>,
+    /// Wad file names listed by worldspawn that weren't found in any search directory.
+    pub missing_wads: Vec<String>,
+    /// Wads that were found but couldn't be read, or that hold textures that
+    /// couldn't be decoded, with the reason.
+    pub unreadable_wads: Vec<(PathBuf, String)>,
+}
+
+impl BspResolvedTextures {
+    /// Returns the names of textures that aren't embedded and weren't found in any listed wad.
+    pub fn missing_textures(&self) -> impl Iterator<Item = &str> {
+        self.textures
+            .iter()
+            .filter(|x| x.source == BspTextureSource::Missing)
+            .map(|x| x.name.as_str())
+    }
+}
+
+/// Embedded textures copied into a WAD3 archive by `extract_embedded_textures`.
+#[derive(Clone)]
+pub struct BspExtractedTextures {
+    pub wad: WadWriter,
+    /// The textures written to the wad, in the order they were written.
+    pub texture_indices: Vec<usize>,
+    /// Embedded textures that weren't written, either because they have no
+    /// palette (Quake maps) or because an earlier texture has the same name.
+    pub skipped: Vec<usize>,
+}
+
+/// Why `BspFile::strip_textures` couldn't add the wad to worldspawn. The map is
+/// left untouched when this is returned.
+#[derive(Debug)]
+pub enum BspStripTexturesError {
+    BadEntities(NullTerminatedStrError),
+    Parse(BspEntityParseError),
+    NoWorldspawn,
+    /// The entities can't be written back, e.g. the wad name contains a quote.
+    Serialize(BspEntitySerializeError),
+}
+
+impl std::fmt::Display for BspStripTexturesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BspStripTexturesError::BadEntities(error) => {
+                write!(f, "entity lump is not valid UTF-8: {}", error)
+            }
+            BspStripTexturesError::Parse(error) => write!(f, "{}", error),
+            BspStripTexturesError::NoWorldspawn => write!(f, "map has no worldspawn entity"),
+            BspStripTexturesError::Serialize(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for BspStripTexturesError {}
+
+/// Splits a worldspawn "wad" value into wad file names, e.g.
+/// `\half-life\valve\halflife.wad;\half-life\valve\decals.wad` becomes
+/// `["halflife.wad", "decals.wad"]`. Like the engine, only the file name of each
+/// path is kept.
+pub fn parse_wad_list(wad_value: &str) -> Vec<String> {
+    let mut wads: Vec<String> = Vec::new();
+    for entry in wad_value.split(';') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let Some(file_name) = Path::new(entry).file_name_pal() else {
+            continue;
+        };
+        if !wads.iter().any(|x| x.eq_ignore_ascii_case(&file_name)) {
+            wads.push(file_name.into_owned());
+        }
+    }
+    wads
+}
+
+impl BspReader<'_> {
+    /// Returns the wad file names listed in the worldspawn entity's "wad" key.
+    pub fn get_wad_list(&self) -> Vec<String> {
+        let entity_string = resolve_map_entity_string(self);
+        let Ok(entities) = BspEntity::parse_entities(&entity_string) else {
+            return Vec::new();
+        };
+        entities
+            .iter()
+            .find(|x| x.classname() == Some("worldspawn"))
+            .and_then(|x| x.get("wad"))
+            .map(parse_wad_list)
+            .unwrap_or_default()
+    }
+
+    /// Decodes every texture in the map. Embedded textures are decoded directly,
+    /// the rest are looked up by name in the wads listed by worldspawn. Each wad
+    /// is searched for in `search_dirs` in order, e.g. the mod directory followed
+    /// by "valve". File names are matched regardless of case.
+    pub fn resolve_textures<P: AsRef<Path>>(&self, search_dirs: &[P]) -> BspResolvedTextures {
+        let mut wads = Vec::new();
+        let mut missing_wads = Vec::new();
+        let mut unreadable_wads = Vec::new();
+        for wad_name in self.get_wad_list() {
+            let wad_path = search_dirs
+                .iter()
+                .find_map(|dir| find_file_ignore_case(dir.as_ref(), &wad_name));
+            let Some(wad_path) = wad_path else {
+                missing_wads.push(wad_name);
+                continue;
+            };
+            match WadArchive::try_open(&wad_path) {
+                Ok(archive) => wads.push((wad_path, archive)),
+                Err(error) => unreadable_wads.push((wad_path, error.to_string())),
+            }
+        }
+
+        let texture_reader = self.read_textures();
+        let mut textures = Vec::with_capacity(texture_reader.len());
+        for i in 0..texture_reader.len() {
+            let Some(texture) = texture_reader.get(i) else {
+                textures.push(BspResolvedTexture {
+                    name: String::new(),
+                    source: BspTextureSource::Missing,
+                    data: None,
+                });
+                continue;
+            };
+            let name = texture.get_image_name().to_owned();
+
+            if texture.has_local_image_data() {
+                textures.push(BspResolvedTexture {
+                    name,
+                    source: BspTextureSource::Embedded,
+                    data: texture.decode(),
+                });
+                continue;
+            }
+
+            // Texture names are case insensitive
+            let found = wads.iter().find_map(|(wad_path, archive)| {
+                let file_info = archive.files.iter().find(|x| {
+                    x.name.eq_ignore_ascii_case(&name)
+                        && (x.texture_type == TextureType::MipmappedImage
+                            || x.texture_type == TextureType::Decal)
+                })?;
+                // Wad entries are plain miptex data, so they're decoded the same
+                // way as embedded textures, which checks every offset
+                let data = BspMipTextureReader::from_bytes(archive.get_raw_data(file_info), true)
+                    .and_then(|x| x.decode());
+                if data.is_none() {
+                    unreadable_wads.push((
+                        wad_path.clone(),
+                        format!("{} is not a valid texture", file_info.name),
+                    ));
+                }
+                Some((wad_path, data?))
+            });
+            if let Some((wad_path, data)) = found {
+                textures.push(BspResolvedTexture {
+                    name,
+                    source: BspTextureSource::Wad(wad_path.clone()),
+                    data: Some(data),
+                });
+            } else {
+                textures.push(BspResolvedTexture {
+                    name,
+                    source: BspTextureSource::Missing,
+                    data: None,
+                });
+            }
+        }
+
+        BspResolvedTextures {
+            textures,
+            missing_wads,
+            unreadable_wads,
+        }
+    }
+
+    /// Copies every texture with local image data into a WAD3 archive. Each entry
+    /// is the texture's miptex data as stored in the map, so palette indices and
+    /// all four mips are kept exactly.
+    pub fn extract_embedded_textures(&self) -> BspExtractedTextures {
+        let mut extracted = BspExtractedTextures {
+            wad: WadWriter::new(),
+            texture_indices: Vec::new(),
+            skipped: Vec::new(),
+        };
+        let mut names: Vec<&str> = Vec::new();
+        let texture_reader = self.read_textures();
+        for i in 0..texture_reader.len() {
+            let Some(texture) = texture_reader.get(i) else {
+                continue;
+            };
+            if !texture.has_local_image_data() {
+                continue;
+            }
+            let name = texture.get_image_name();
+            let Some(palette) = texture.read_palette() else {
+                extracted.skipped.push(i);
+                continue;
+            };
+            // Texture names are case insensitive
+            if names.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+                extracted.skipped.push(i);
+                continue;
+            }
+
+            // The palette comes right after the last mip and its color count
+            let header = texture.header();
+            let last_mip_len = (header.width as usize / 8) * (header.height as usize / 8);
+            let palette_end =
+                header.offsets[3] as usize + last_mip_len + 2 + palette.raw_data().len();
+            let mut data = texture.raw_data()[..palette_end].to_vec();
+            data.resize(palette_end.next_multiple_of(4), 0);
+
+            extracted
+                .wad
+                .add_file(name, TextureType::MipmappedImage, data);
+            extracted.texture_indices.push(i);
+            names.push(name);
+        }
+        extracted
+    }
+}
+
+impl BspFile {
+    /// Removes the image data of the given textures, leaving the bare miptex
+    /// header the compilers write for textures that load from a wad. `wad_name`
+    /// is added to worldspawn's "wad" key so the engine looks for them there.
+    pub fn strip_textures(
+        &mut self,
+        texture_indices: &[usize],
+        wad_name: &str,
+    ) -> Result<(), BspStripTexturesError> {
+        // Stripped textures can't load without the wad, so the entities are
+        // updated first and nothing changes if that fails
+        let entity_string = null_terminated_bytes_to_str(&self.entities)
+            .map_err(BspStripTexturesError::BadEntities)?
+            .to_owned();
+        let mut entities =
+            BspEntity::parse_entities(&entity_string).map_err(BspStripTexturesError::Parse)?;
+        let worldspawn = entities
+            .iter_mut()
+            .find(|x| x.classname() == Some("worldspawn"))
+            .ok_or(BspStripTexturesError::NoWorldspawn)?;
+        let wads = worldspawn.get("wad").unwrap_or("");
+        if !parse_wad_list(wads)
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(wad_name))
+        {
+            let wads = wads.trim_end_matches(';');
+            let wads = if wads.is_empty() {
+                wad_name.to_owned()
+            } else {
+                format!("{};{}", wads, wad_name)
+            };
+            worldspawn.set("wad", wads);
+            let entity_string = BspEntity::serialize_entities(&entities)
+                .map_err(BspStripTexturesError::Serialize)?;
+            self.set_entities_str(&entity_string);
+        }
+
+        let header_len = size_of::<BspMipTextureHeader>();
+        let offsets_start = header_len - size_of::<[u32; 4]>();
+        for texture_index in texture_indices {
+            let Some(Some(data)) = self.textures.get_mut(*texture_index) else {
+                continue;
+            };
+            if data.len() < header_len {
+                continue;
+            }
+            data.truncate(header_len);
+            data[offsets_start..].fill(0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_maps;
+    use super::*;
+
+    // A texture stored in a wad, so only the name and size
+    fn miptex_header(name: &str) -> Vec<u8> {
+        let mut data = vec![0u8; size_of::<BspMipTextureHeader>()];
+        data[..name.len()].copy_from_slice(name.as_bytes());
+        data[16..20].copy_from_slice(&16u32.to_le_bytes());
+        data[20..24].copy_from_slice(&16u32.to_le_bytes());
+        data
+    }
+
+    // A 16x16 texture with all four mips and a palette, padded to 4 bytes the
+    // way compilers store it
+    fn embedded_miptex(name: &str) -> Vec<u8> {
+        let mut data = miptex_header(name);
+        let mut offset = data.len() as u32;
+        for (i, size) in [256u32, 64, 16, 4].into_iter().enumerate() {
+            data[24 + i * 4..28 + i * 4].copy_from_slice(&offset.to_le_bytes());
+            offset += size;
+        }
+        data.extend((0..340).map(|x| x as u8));
+        data.extend_from_slice(&256u16.to_le_bytes());
+        data.extend((0..768).map(|x| (x * 7) as u8));
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+
+    #[test]
+    fn extracted_textures_round_trip() {
+        let mut file = test_maps::single_face_map(8.0);
+        let textures = [embedded_miptex("brick"), embedded_miptex("{fence")];
+        file.textures.push(Some(textures[0].clone()));
+        file.textures.push(Some(miptex_header("from_wad")));
+        file.textures.push(Some(textures[1].clone()));
+        let extracted = test_maps::read(&file).extract_embedded_textures();
+        assert_eq!(extracted.texture_indices, [0, 2]);
+        assert!(extracted.skipped.is_empty());
+
+        let archive = WadArchive::try_from_bytes(extracted.wad.to_bytes()).unwrap();
+        let names: Vec<_> = archive.files.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["brick", "{fence"]);
+        for (file_info, texture) in archive.files.iter().zip(&textures) {
+            assert_eq!(file_info.texture_type, TextureType::MipmappedImage);
+            assert_eq!(archive.get_raw_data(file_info), texture.as_slice());
+        }
+    }
+
+    #[test]
+    fn strip_textures() {
+        let mut file = test_maps::single_face_map(8.0);
+        file.textures.push(Some(embedded_miptex("brick")));
+        file.set_entities_str("{\n\"classname\" \"worldspawn\"\n\"wad\" \"halflife.wad;\"\n}\n");
+        file.strip_textures(&[0], "brick.wad").unwrap();
+
+        let reader = test_maps::read(&file);
+        assert_eq!(reader.get_wad_list(), ["halflife.wad", "brick.wad"]);
+        assert_eq!(
+            file.textures[0].as_deref(),
+            Some(miptex_header("brick").as_slice())
+        );
+
+        // Already listed, so worldspawn is left alone
+        let entities = file.entities.clone();
+        file.strip_textures(&[0], "BRICK.WAD").unwrap();
+        assert_eq!(file.entities, entities);
+    }
+
+    #[test]
+    fn strip_textures_without_worldspawn() {
+        let mut file = test_maps::single_face_map(8.0);
+        file.textures.push(Some(embedded_miptex("brick")));
+        file.set_entities_str("{\n\"classname\" \"info_null\"\n}\n");
+        let before = file.clone();
+        let result = file.strip_textures(&[0], "brick.wad");
+        assert!(matches!(result, Err(BspStripTexturesError::NoWorldspawn)));
+        assert_eq!(file.entities, before.entities);
+        assert_eq!(file.textures, before.textures);
+    }
+
+    #[test]
+    fn strip_textures_with_bad_wad_name() {
+        let mut file = test_maps::single_face_map(8.0);
+        file.textures.push(Some(embedded_miptex("brick")));
+        let before = file.clone();
+        let result = file.strip_textures(&[0], "brick\".wad");
+        assert!(matches!(result, Err(BspStripTexturesError::Serialize(_))));
+        assert_eq!(file.entities, before.entities);
+        assert_eq!(file.textures, before.textures);
+    }
+
+    #[test]
+    fn malformed_wad_texture() {
+        let dir = std::env::temp_dir().join(format!("gsparser_wad_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Mip offsets past the end of the entry
+        let mut bad = miptex_header("bad");
+        bad[24..28].copy_from_slice(&0xFFFF_FF00u32.to_le_bytes());
+        let mut wad = WadWriter::new();
+        wad.add_file("bad", TextureType::MipmappedImage, bad);
+        std::fs::write(dir.join("test.wad"), wad.to_bytes()).unwrap();
+
+        let mut file = test_maps::single_face_map(8.0);
+        file.textures.push(Some(miptex_header("bad")));
+        file.set_entities_str("{\n\"classname\" \"worldspawn\"\n\"wad\" \"test.wad\"\n}\n");
+        let textures = test_maps::read(&file).resolve_textures(&[&dir]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(textures.textures[0].source, BspTextureSource::Missing);
+        assert!(textures.textures[0].data.is_none());
+        assert_eq!(textures.unreadable_wads.len(), 1);
+        assert_eq!(textures.unreadable_wads[0].0, dir.join("test.wad"));
+    }
+}
@@ -0,0 +1,100 @@
+// Convex polygon helpers shared by portal and hull brush building. Everything is
+// in f64 so repeated splitting doesn't drift.
+
+// Points this close to a plane count as on it when splitting windings
+const SPLIT_EPSILON: f64 = 0.01;
+
+pub(crate) type Winding = Vec<[f64; 3]>;
+
+pub(crate) fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub(crate) fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// A square on the plane, `size` units from the plane's origin in each direction
+pub(crate) fn base_winding(normal: [f64; 3], dist: f64, size: f64) -> Winding {
+    let major_axis = (0..3)
+        .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+        .unwrap();
+    let mut up = if major_axis == 2 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 0.0, 1.0]
+    };
+    let d = dot(up, normal);
+    up = [0, 1, 2].map(|i| up[i] - normal[i] * d);
+    let length = dot(up, up).sqrt();
+    up = up.map(|x| x / length * size);
+    let right = cross(up, normal);
+    let origin = normal.map(|x| x * dist);
+    vec![
+        [0, 1, 2].map(|i| origin[i] - right[i] + up[i]),
+        [0, 1, 2].map(|i| origin[i] + right[i] + up[i]),
+        [0, 1, 2].map(|i| origin[i] + right[i] - up[i]),
+        [0, 1, 2].map(|i| origin[i] - right[i] - up[i]),
+    ]
+}
+
+// Returns the parts of the winding in front of and behind the plane. A winding
+// lying on the plane goes to the back, like the compilers do.
+pub(crate) fn split_winding(
+    winding: &Winding,
+    normal: [f64; 3],
+    dist: f64,
+) -> (Option<Winding>, Option<Winding>) {
+    let dists: Vec<_> = winding.iter().map(|x| dot(*x, normal) - dist).collect();
+    let has_front = dists.iter().any(|x| *x > SPLIT_EPSILON);
+    let has_back = dists.iter().any(|x| *x < -SPLIT_EPSILON);
+    if !has_front {
+        return (None, Some(winding.clone()));
+    }
+    if !has_back {
+        return (Some(winding.clone()), None);
+    }
+
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    for i in 0..winding.len() {
+        let point = winding[i];
+        let d = dists[i];
+        if d >= -SPLIT_EPSILON {
+            front.push(point);
+        }
+        if d <= SPLIT_EPSILON {
+            back.push(point);
+        }
+
+        let next_index = (i + 1) % winding.len();
+        let next_d = dists[next_index];
+        let crosses = (d > SPLIT_EPSILON && next_d < -SPLIT_EPSILON)
+            || (d < -SPLIT_EPSILON && next_d > SPLIT_EPSILON);
+        if crosses {
+            let next = winding[next_index];
+            let t = d / (d - next_d);
+            let mid = [0, 1, 2].map(|j| point[j] + (next[j] - point[j]) * t);
+            front.push(mid);
+            back.push(mid);
+        }
+    }
+    let front = Some(front).filter(|x| x.len() >= 3);
+    let back = Some(back).filter(|x| x.len() >= 3);
+    (front, back)
+}
+
+pub(crate) fn winding_area(winding: &Winding) -> f64 {
+    let mut total = [0.0; 3];
+    for i in 1..winding.len().saturating_sub(1) {
+        let a = [0, 1, 2].map(|j| winding[i][j] - winding[0][j]);
+        let b = [0, 1, 2].map(|j| winding[i + 1][j] - winding[0][j]);
+        let c = cross(a, b);
+        total = [0, 1, 2].map(|j| total[j] + c[j]);
+    }
+    dot(total, total).sqrt() * 0.5
+}
@@ -9,6 +9,7 @@ pub mod mdl;
 pub mod path;
 pub mod resource;
 pub mod sav;
+pub mod scene;
 pub mod spr;
 pub mod sprite_info;
 pub mod steam;
@@ -1,4 +1,7 @@
-use std::{borrow::Cow, path::Path};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
 
 // Looks like std::path::Path is system specific. On macOS and Linux, using '\' instead of '/' in
 // the path causes file_name and file_stem to return the entire string.
@@ -36,3 +39,26 @@ impl PathPal for Path {
         Some(Cow::Owned(name))
     }
 }
+
+/// Finds `relative` in `dir`, matching each path component regardless of case
+/// the way the game does on Windows. Either slash may separate components.
+pub fn find_file_ignore_case(dir: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    for component in relative.split(['/', '\\']).filter(|x| !x.is_empty()) {
+        let exact = path.join(component);
+        if exact.exists() {
+            path = exact;
+            continue;
+        }
+        path = std::fs::read_dir(&path)
+            .ok()?
+            .filter_map(|x| x.ok())
+            .find(|x| {
+                x.file_name()
+                    .to_str()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(component))
+            })?
+            .path();
+    }
+    Some(path).filter(|x| x.is_file())
+}
@@ -0,0 +1,306 @@
+use std::path::{Path, PathBuf};
+
+use crate::bsp::{
+    BspEntity, BspEntityParseError, BspModelInstances, BspReader, BspTransform, FromValue,
+};
+use crate::path::find_file_ignore_case;
+use crate::util::resolve_map_entity_string;
+
+// Models set by each monster's Spawn in the Half-Life SDK, used when the entity
+// has no "model" key. Xen's plants are monsters too. monster_generic and
+// monster_furniture always take their model from the key.
+const MONSTER_MODELS: [(&str, &str); 46] = [
+    ("monster_alien_controller", "models/controller.mdl"),
+    ("monster_alien_grunt", "models/agrunt.mdl"),
+    ("monster_alien_slave", "models/islave.mdl"),
+    ("monster_apache", "models/apache.mdl"),
+    ("monster_babycrab", "models/baby_headcrab.mdl"),
+    ("monster_babygarg", "models/babygarg.mdl"),
+    ("monster_barnacle", "models/barnacle.mdl"),
+    ("monster_barney", "models/barney.mdl"),
+    ("monster_barney_dead", "models/barney.mdl"),
+    ("monster_bigmomma", "models/big_mom.mdl"),
+    ("monster_bloater", "models/floater.mdl"),
+    ("monster_bullchicken", "models/bullsquid.mdl"),
+    ("monster_cockroach", "models/roach.mdl"),
+    ("monster_flyer", "models/boid.mdl"),
+    ("monster_flyer_flock", "models/boid.mdl"),
+    ("monster_gargantua", "models/garg.mdl"),
+    ("monster_gman", "models/gman.mdl"),
+    ("monster_headcrab", "models/headcrab.mdl"),
+    ("monster_hevsuit_dead", "models/player.mdl"),
+    ("monster_hgrunt_dead", "models/hgrunt.mdl"),
+    ("monster_houndeye", "models/houndeye.mdl"),
+    ("monster_human_assassin", "models/hassassin.mdl"),
+    ("monster_human_grunt", "models/hgrunt.mdl"),
+    ("monster_ichthyosaur", "models/icky.mdl"),
+    ("monster_leech", "models/leech.mdl"),
+    ("monster_miniturret", "models/miniturret.mdl"),
+    ("monster_nihilanth", "models/nihilanth.mdl"),
+    ("monster_osprey", "models/osprey.mdl"),
+    ("monster_rat", "models/bigrat.mdl"),
+    ("monster_satchel", "models/w_satchel.mdl"),
+    ("monster_scientist", "models/scientist.mdl"),
+    ("monster_scientist_dead", "models/scientist.mdl"),
+    ("monster_sentry", "models/sentry.mdl"),
+    ("monster_sitting_scientist", "models/scientist.mdl"),
+    ("monster_snark", "models/w_squeak.mdl"),
+    ("monster_tentacle", "models/tentacle2.mdl"),
+    ("monster_tentaclemaw", "models/tentacle3.mdl"),
+    ("monster_tripmine", "models/v_tripmine.mdl"),
+    ("monster_turret", "models/turret.mdl"),
+    ("monster_zombie", "models/zombie.mdl"),
+    ("xen_hair", "models/hair.mdl"),
+    ("xen_plantlight", "models/light.mdl"),
+    ("xen_spore_large", "models/fungus(large).mdl"),
+    ("xen_spore_medium", "models/fungus.mdl"),
+    ("xen_spore_small", "models/fungus(small).mdl"),
+    ("xen_tree", "models/tree.mdl"),
+];
+
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SceneRenderMode {
+    Normal = 0,
+    Color = 1,
+    Texture = 2,
+    Glow = 3,
+    Solid = 4,
+    Additive = 5,
+}
+
+impl FromValue<i32> for SceneRenderMode {
+    fn from_value(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(SceneRenderMode::Normal),
+            1 => Some(SceneRenderMode::Color),
+            2 => Some(SceneRenderMode::Texture),
+            3 => Some(SceneRenderMode::Glow),
+            4 => Some(SceneRenderMode::Solid),
+            5 => Some(SceneRenderMode::Additive),
+            _ => None,
+        }
+    }
+}
+
+/// The render keys shared by every entity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneRender {
+    pub mode: SceneRenderMode,
+    /// 0-255, only used by modes other than normal.
+    pub amount: f32,
+    /// 0-255 per channel.
+    pub color: [f32; 3],
+    pub fx: i32,
+}
+
+impl SceneRender {
+    pub const NORMAL: SceneRender = SceneRender {
+        mode: SceneRenderMode::Normal,
+        amount: 0.0,
+        color: [0.0; 3],
+        fx: 0,
+    };
+
+    pub fn from_entity(entity: &BspEntity) -> Self {
+        let parse_i32 = |key: &str| entity.get(key).and_then(|x| x.trim().parse::<i32>().ok());
+        Self {
+            mode: parse_i32("rendermode")
+                .and_then(SceneRenderMode::from_value)
+                .unwrap_or(SceneRenderMode::Normal),
+            amount: entity
+                .get("renderamt")
+                .and_then(|x| x.trim().parse::<f32>().ok())
+                .unwrap_or(0.0),
+            color: entity.get_vector("rendercolor").unwrap_or([0.0; 3]),
+            fx: parse_i32("renderfx").unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneInstanceKind {
+    /// The map's world geometry, model 0.
+    World,
+    /// A brush model stored in the map.
+    Brush { model_index: usize },
+    /// A studio model (.mdl).
+    Studio { body: i32, skin: i32, sequence: i32 },
+    /// A sprite (.spr). A scale of 0 is drawn at full size, and a frame rate of
+    /// 0 doesn't animate.
+    Sprite { scale: f32, frame_rate: f32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneInstance {
+    /// `None` for the world if the map has no worldspawn entity.
+    pub entity_index: Option<usize>,
+    pub classname: String,
+    pub kind: SceneInstanceKind,
+    pub transform: BspTransform,
+    pub render: SceneRender,
+    /// The model as the entity names it, e.g. "*3" or "models/scientist.mdl".
+    pub model: String,
+    /// Where the model's file was found. Brush models are part of the map, so
+    /// this is the map's own path for them. `None` for files missing from every
+    /// search directory.
+    pub path: Option<PathBuf>,
+}
+
+impl SceneInstance {
+    /// Returns true for studio models and sprites whose file wasn't found.
+    pub fn is_missing(&self) -> bool {
+        self.path.is_none()
+            && matches!(
+                self.kind,
+                SceneInstanceKind::Studio { .. } | SceneInstanceKind::Sprite { .. }
+            )
+    }
+}
+
+/// Everything a map draws: the world, brush entities, and point entities with a
+/// studio model or sprite. Triggers are left out since the game never draws
+/// them.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub instances: Vec<SceneInstance>,
+}
+
+impl Scene {
+    /// Files are looked up in `search_dirs` in order, e.g. the mod directory
+    /// followed by "valve". File names are matched regardless of case.
+    pub fn new<P: AsRef<Path>>(
+        reader: &BspReader,
+        map_path: &Path,
+        entities: &[BspEntity],
+        search_dirs: &[P],
+    ) -> Self {
+        let model_instances = BspModelInstances::new(reader, entities);
+        let mut instances = Vec::new();
+        for (entity_index, entity) in entities.iter().enumerate() {
+            let classname = entity.classname().unwrap_or("");
+            if classname.starts_with("trigger_") {
+                continue;
+            }
+
+            if classname == "worldspawn" || entity.get("model").is_some_and(|x| x.starts_with('*'))
+            {
+                let Some(model_instance) = model_instances.get_by_entity(entity_index) else {
+                    continue;
+                };
+                let (kind, render) = if model_instance.model_index == 0 {
+                    (SceneInstanceKind::World, SceneRender::NORMAL)
+                } else {
+                    (
+                        SceneInstanceKind::Brush {
+                            model_index: model_instance.model_index,
+                        },
+                        SceneRender::from_entity(entity),
+                    )
+                };
+                instances.push(SceneInstance {
+                    entity_index: Some(entity_index),
+                    classname: classname.to_owned(),
+                    kind,
+                    transform: model_instance.transform,
+                    render,
+                    model: format!("*{}", model_instance.model_index),
+                    path: Some(map_path.to_owned()),
+                });
+                continue;
+            }
+
+            let model = entity.get("model").filter(|x| !x.is_empty()).or_else(|| {
+                MONSTER_MODELS
+                    .iter()
+                    .find(|(name, _)| *name == classname)
+                    .map(|(_, model)| *model)
+            });
+            let Some(model) = model else {
+                continue;
+            };
+            let extension = Path::new(model)
+                .extension()
+                .and_then(|x| x.to_str())
+                .unwrap_or("");
+            let parse_i32 = |key: &str| {
+                entity
+                    .get(key)
+                    .and_then(|x| x.trim().parse::<i32>().ok())
+                    .unwrap_or(0)
+            };
+            let parse_f32 = |key: &str| {
+                entity
+                    .get(key)
+                    .and_then(|x| x.trim().parse::<f32>().ok())
+                    .unwrap_or(0.0)
+            };
+            let kind = if extension.eq_ignore_ascii_case("mdl") {
+                SceneInstanceKind::Studio {
+                    body: parse_i32("body"),
+                    skin: parse_i32("skin"),
+                    sequence: parse_i32("sequence"),
+                }
+            } else if extension.eq_ignore_ascii_case("spr") {
+                SceneInstanceKind::Sprite {
+                    scale: parse_f32("scale"),
+                    frame_rate: parse_f32("framerate"),
+                }
+            } else {
+                continue;
+            };
+            instances.push(SceneInstance {
+                entity_index: Some(entity_index),
+                classname: classname.to_owned(),
+                kind,
+                transform: BspTransform::from_entity(entity),
+                render: SceneRender::from_entity(entity),
+                model: model.to_owned(),
+                path: find_file(search_dirs, model),
+            });
+        }
+
+        // Maps without a worldspawn still have a world
+        if !instances.iter().any(|x| x.kind == SceneInstanceKind::World)
+            && !reader.read_models().is_empty()
+        {
+            instances.insert(
+                0,
+                SceneInstance {
+                    entity_index: None,
+                    classname: "worldspawn".to_owned(),
+                    kind: SceneInstanceKind::World,
+                    transform: BspTransform::IDENTITY,
+                    render: SceneRender::NORMAL,
+                    model: "*0".to_owned(),
+                    path: Some(map_path.to_owned()),
+                },
+            );
+        }
+
+        Self { instances }
+    }
+
+    pub fn from_reader<P: AsRef<Path>>(
+        reader: &BspReader,
+        map_path: &Path,
+        search_dirs: &[P],
+    ) -> Result<Self, BspEntityParseError> {
+        let entity_string = resolve_map_entity_string(reader);
+        let entities = BspEntity::parse_entities(&entity_string)?;
+        Ok(Self::new(reader, map_path, &entities, search_dirs))
+    }
+
+    /// Returns the studio models and sprites whose file wasn't found.
+    pub fn missing_files(&self) -> impl Iterator<Item = &SceneInstance> {
+        self.instances.iter().filter(|x| x.is_missing())
+    }
+}
+
+// Model paths are relative to the game directory, may use either slash and are
+// matched regardless of case like the game does
+fn find_file<P: AsRef<Path>>(search_dirs: &[P], file: &str) -> Option<PathBuf> {
+    search_dirs
+        .iter()
+        .find_map(|dir| find_file_ignore_case(dir.as_ref(), file))
+}
@@ -3,12 +3,11 @@ extern crate byteorder;
 extern crate image;
 extern crate serde;
 
-use std::fs::File;
-use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::str;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::Deserialize;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -84,6 +83,9 @@ struct WadHeader {
     dir_offset: u32,
 }
 
+// The size of a WadDirectory as stored in the file
+const WAD_DIRECTORY_SIZE: usize = 32;
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, Deserialize)]
 struct WadDirectory {
@@ -129,63 +131,106 @@ impl FontHeader {
     }
 }
 
-impl WadArchive {
-    pub fn open<P: AsRef<Path>>(wad_path: P) -> WadArchive {
-        let file = File::open(wad_path).unwrap();
-        let file_size = file.metadata().unwrap().len();
-        let mut file = BufReader::new(file);
+#[derive(Debug)]
+pub enum WadReadError {
+    Io(std::io::Error),
+    BadMagic([u8; 4]),
+    TruncatedHeader,
+    DirectoryOutOfBounds,
+    FileOutOfBounds { name: String },
+    UnknownFileType { name: String, dir_type: u8 },
+}
 
-        let file_infos = Self::read_file_infos(&mut file);
+impl std::fmt::Display for WadReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WadReadError::Io(error) => write!(f, "{}", error),
+            WadReadError::BadMagic(magic) => write!(f, "unknown magic {:?}", magic),
+            WadReadError::TruncatedHeader => write!(f, "header is truncated"),
+            WadReadError::DirectoryOutOfBounds => {
+                write!(f, "directory does not fit in the file")
+            }
+            WadReadError::FileOutOfBounds { name } => {
+                write!(f, "{} does not fit in the file", name)
+            }
+            WadReadError::UnknownFileType { name, dir_type } => {
+                write!(f, "{} has unknown type 0x{:X}", name, dir_type)
+            }
+        }
+    }
+}
 
-        file.seek(SeekFrom::Start(0)).unwrap();
-        let mut file_data = vec![0u8; file_size as usize];
-        file.read(&mut file_data).unwrap();
+impl std::error::Error for WadReadError {}
 
-        WadArchive {
-            files: file_infos,
-            raw_data: file_data,
-        }
+impl From<std::io::Error> for WadReadError {
+    fn from(error: std::io::Error) -> Self {
+        WadReadError::Io(error)
+    }
+}
+
+impl WadArchive {
+    pub fn open<P: AsRef<Path>>(wad_path: P) -> WadArchive {
+        Self::try_open(wad_path).unwrap()
+    }
+
+    /// Like `open`, but returns an error for unreadable or malformed files
+    /// instead of panicking.
+    pub fn try_open<P: AsRef<Path>>(wad_path: P) -> Result<WadArchive, WadReadError> {
+        let file_data = std::fs::read(wad_path)?;
+        Self::try_from_bytes(file_data)
     }
 
     pub fn from_bytes(wad_bytes: Vec<u8>) -> Self {
-        let mut reader = std::io::Cursor::new(&wad_bytes);
-        let file_infos = Self::read_file_infos(&mut reader);
-        Self {
+        Self::try_from_bytes(wad_bytes).unwrap()
+    }
+
+    pub fn try_from_bytes(wad_bytes: Vec<u8>) -> Result<Self, WadReadError> {
+        let file_infos = Self::read_file_infos(&wad_bytes)?;
+        Ok(Self {
             files: file_infos,
             raw_data: wad_bytes,
-        }
+        })
     }
 
-    fn read_file_infos<R: Read + Seek>(mut reader: R) -> Vec<WadFileInfo> {
-        let header: WadHeader = bincode::deserialize_from(&mut reader).unwrap();
-        assert_eq!(header.magic[0], 87); // 'W' in ASCII
-        assert_eq!(header.magic[1], 65); // 'A' in ASCII
-        assert_eq!(header.magic[2], 68); // 'D' in ASCII
-        assert_eq!(header.magic[3], 51); // '3' in ASCII
+    fn read_file_infos(data: &[u8]) -> Result<Vec<WadFileInfo>, WadReadError> {
+        let header: WadHeader =
+            bincode::deserialize(data).map_err(|_| WadReadError::TruncatedHeader)?;
+        if &header.magic != b"WAD3" {
+            return Err(WadReadError::BadMagic(header.magic));
+        }
+
+        let dir_size = WAD_DIRECTORY_SIZE;
+        let dir_start = header.dir_offset as usize;
+        let directory = (header.num_dir as usize)
+            .checked_mul(dir_size)
+            .and_then(|x| x.checked_add(dir_start))
+            .and_then(|dir_end| data.get(dir_start..dir_end))
+            .ok_or(WadReadError::DirectoryOutOfBounds)?;
 
         let mut file_infos = Vec::new();
-        reader
-            .seek(SeekFrom::Start(header.dir_offset as u64))
-            .unwrap();
-        for _i in 0..header.num_dir {
-            let wad_dir: WadDirectory = bincode::deserialize_from(&mut reader).unwrap();
-            let name = str::from_utf8(&wad_dir.name).unwrap();
-            let name = name.trim_matches(char::from(0));
+        for entry in directory.chunks_exact(dir_size) {
+            let wad_dir: WadDirectory = bincode::deserialize(entry).unwrap();
+            let name = String::from_utf8_lossy(&wad_dir.name);
+            let name = name.split('\0').next().unwrap_or("").to_string();
             let texture_type = match wad_dir.dir_type {
                 0x40 => TextureType::Decal,
                 0x42 => TextureType::Image,
                 0x43 => TextureType::MipmappedImage,
                 0x46 => TextureType::Font,
-                _ => panic!("unknown dir type! {} - 0x{:X}", name, wad_dir.dir_type),
+                dir_type => return Err(WadReadError::UnknownFileType { name, dir_type }),
             };
+            let file_end = wad_dir.file_position as u64 + wad_dir.disk_size as u64;
+            if file_end > data.len() as u64 {
+                return Err(WadReadError::FileOutOfBounds { name });
+            }
             file_infos.push(WadFileInfo {
-                name: name.to_string(),
-                texture_type: texture_type,
+                name,
+                texture_type,
                 info: wad_dir,
             });
         }
 
-        file_infos
+        Ok(file_infos)
     }
 
     pub fn decode_decal(&self, file_info: &WadFileInfo) -> MipmapedTextureData {
@@ -441,14 +486,81 @@ impl WadArchive {
         }
     }
 
+    /// Returns a file's data exactly as stored in the archive.
+    pub fn get_raw_data(&self, file_info: &WadFileInfo) -> &[u8] {
+        let start_index = file_info.info.file_position as usize;
+        let end_index = start_index + file_info.info.disk_size as usize;
+        &self.raw_data[start_index..end_index]
+    }
+
     fn get_file_data(&self, file_info: &WadFileInfo) -> Cursor<&[u8]> {
-        let file_data = {
-            let raw_data = self.raw_data.as_slice();
-            let start_index = file_info.info.file_position as usize;
-            let end_index = start_index + file_info.info.disk_size as usize;
-            &raw_data[start_index..end_index]
-        };
-        Cursor::new(file_data)
+        Cursor::new(self.get_raw_data(file_info))
+    }
+}
+
+/// Builds a WAD3 archive out of already encoded files, e.g. miptex data taken
+/// from a bsp.
+#[derive(Clone, Default)]
+pub struct WadWriter {
+    files: Vec<(String, TextureType, Vec<u8>)>,
+}
+
+impl WadWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file. Names are truncated to the 15 characters the directory has
+    /// room for.
+    pub fn add_file(&mut self, name: &str, texture_type: TextureType, data: Vec<u8>) {
+        self.files.push((name.to_owned(), texture_type, data));
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let bytes = self.to_bytes();
+        writer.write_all(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header_size = 12;
+        let mut data = vec![0u8; header_size];
+        let mut positions = Vec::with_capacity(self.files.len());
+        for (_, _, file_data) in &self.files {
+            positions.push(data.len() as u32);
+            data.extend_from_slice(file_data);
+            // Files start on 4 byte boundaries like the tools write them
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+
+        let dir_offset = data.len() as u32;
+        for ((name, texture_type, file_data), position) in self.files.iter().zip(positions) {
+            data.write_u32::<LittleEndian>(position).unwrap();
+            data.write_u32::<LittleEndian>(file_data.len() as u32)
+                .unwrap();
+            data.write_u32::<LittleEndian>(file_data.len() as u32)
+                .unwrap();
+            data.write_u8(*texture_type as u8).unwrap();
+            // Not compressed, then padding
+            data.write_u8(0).unwrap();
+            data.write_i16::<LittleEndian>(0).unwrap();
+            let mut name_bytes = [0u8; 16];
+            let len = name.len().min(15);
+            name_bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+            data.extend_from_slice(&name_bytes);
+        }
+
+        data[0..4].copy_from_slice(b"WAD3");
+        data[4..8].copy_from_slice(&(self.files.len() as u32).to_le_bytes());
+        data[8..12].copy_from_slice(&dir_offset.to_le_bytes());
+        data
     }
 }
 